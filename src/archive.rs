@@ -0,0 +1,191 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+/// Separates the path of an archive from the path of a file inside of it,
+/// as in `book.epub!/chapter3.xhtml`
+pub const ENTRY_SEPARATOR: &str = "!/";
+
+/// Extensions of the archives documents are read from, see [`ArchiveKind::of`]
+pub const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "epub", "tar", "tar.gz", "tgz"];
+
+/// Extensions of the chapters of an EPUB
+const EPUB_ENTRY_EXTENSIONS: &[&str] = &["xhtml", "html", "htm"];
+
+#[derive(Clone, Copy)]
+pub enum ArchiveKind {
+    /// Zip files, EPUBs included
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn invalid_data(err: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+impl ArchiveKind {
+    pub fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            return Some(Self::TarGz);
+        }
+        match name.rsplit_once('.')?.1 {
+            "zip" | "epub" => Some(Self::Zip),
+            "tar" => Some(Self::Tar),
+            _ => None,
+        }
+    }
+
+    /// Whether the archive at `path` may hold documents with one of the
+    /// `enabled` extensions. Only EPUBs are known to hold particular ones,
+    /// other archives are only opened when their own extension is enabled.
+    pub fn may_contain(path: &Path, enabled: &HashSet<String>) -> bool {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+        let name = name.to_ascii_lowercase();
+        let listed = ARCHIVE_EXTENSIONS
+            .iter()
+            .any(|ext| name.ends_with(&format!(".{ext}")) && enabled.contains(*ext));
+        let epub_entries = name.ends_with(".epub")
+            && EPUB_ENTRY_EXTENSIONS
+                .iter()
+                .any(|ext| enabled.contains(*ext));
+        listed || epub_entries
+    }
+
+    /// Call `visit` with the name and content of every file in the archive
+    /// for which `wanted` returns true
+    pub fn for_each_entry(
+        self,
+        content: &[u8],
+        wanted: impl Fn(&str) -> bool,
+        mut visit: impl FnMut(&str, Vec<u8>),
+    ) -> io::Result<()> {
+        match self {
+            Self::Zip => {
+                let mut archive = ZipArchive::new(Cursor::new(content)).map_err(invalid_data)?;
+                for i in 0..archive.len() {
+                    let mut file = archive.by_index(i).map_err(invalid_data)?;
+                    if !file.is_file() || !wanted(file.name()) {
+                        continue;
+                    }
+                    let size = file.size();
+                    let bytes = read_entry_bytes(&mut file, size, content.len())?;
+                    visit(file.name(), bytes);
+                }
+                Ok(())
+            }
+            Self::Tar => for_each_tar_entry(content, content.len(), wanted, visit),
+            Self::TarGz => {
+                for_each_tar_entry(GzDecoder::new(content), content.len(), wanted, visit)
+            }
+        }
+    }
+
+    /// Content of the file called `name` inside the archive
+    pub fn read_entry(self, content: &[u8], name: &str) -> io::Result<Vec<u8>> {
+        let mut found = None;
+        self.for_each_entry(
+            content,
+            |entry| entry == name,
+            |_, bytes| {
+                found.get_or_insert(bytes);
+            },
+        )?;
+        found.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no {name} in the archive"))
+        })
+    }
+}
+
+/// Content of the file at `path`, or of the entry of the archive at `path`
+/// when `entry` is given as `!/name`, along with the path its type is told by
+pub fn read_file_or_entry<'a>(
+    path: &'a Path,
+    entry: Option<&'a str>,
+) -> io::Result<(Vec<u8>, &'a Path)> {
+    let content = fs::read(path)?;
+    match entry.and_then(|entry| entry.strip_prefix(ENTRY_SEPARATOR)) {
+        Some(name) => {
+            let kind = ArchiveKind::of(path).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "document is not in an archive")
+            })?;
+            Ok((kind.read_entry(&content, name)?, Path::new(name)))
+        }
+        None => Ok((content, path)),
+    }
+}
+
+/// Content of an archive entry whose header claims `size` bytes. Headers
+/// can claim anything, so no more than `limit` bytes, the size of the
+/// archive, are reserved up front and reading stops at the claimed size.
+fn read_entry_bytes(entry: impl Read, size: u64, limit: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(size.min(limit as u64) as usize);
+    entry.take(size).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn for_each_tar_entry(
+    content: impl Read,
+    limit: usize,
+    wanted: impl Fn(&str) -> bool,
+    mut visit: impl FnMut(&str, Vec<u8>),
+) -> io::Result<()> {
+    let mut archive = tar::Archive::new(content);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if !wanted(&name) {
+            continue;
+        }
+        let size = entry.size();
+        visit(&name, read_entry_bytes(&mut entry, size, limit)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_sizes_claimed_by_headers_are_not_trusted() {
+        let mut header = tar::Header::new_gnu();
+        header.set_path("huge.txt").unwrap();
+        header.set_size(8 << 30);
+        header.set_cksum();
+        let mut content = header.as_bytes().to_vec();
+        content.extend_from_slice(&[b'a'; 512]);
+
+        let mut sizes = Vec::new();
+        let result =
+            ArchiveKind::Tar.for_each_entry(&content, |_| true, |_, bytes| sizes.push(bytes.len()));
+        // The entry is cut short, whatever the reader makes of it
+        assert!(result.is_err() || sizes.iter().all(|size| *size <= content.len()));
+    }
+
+    #[test]
+    fn archives_opened_for_enabled_extensions() {
+        let enabled = |exts: &[&str]| exts.iter().map(|ext| ext.to_string()).collect();
+        let md = enabled(&["md"]);
+        assert!(!ArchiveKind::may_contain(Path::new("a.zip"), &md));
+        assert!(!ArchiveKind::may_contain(Path::new("a.epub"), &md));
+        let md_zip = enabled(&["md", "zip", "tar.gz"]);
+        assert!(ArchiveKind::may_contain(Path::new("a.ZIP"), &md_zip));
+        assert!(ArchiveKind::may_contain(Path::new("a.tar.gz"), &md_zip));
+        assert!(!ArchiveKind::may_contain(Path::new("a.tgz"), &md_zip));
+        assert!(ArchiveKind::may_contain(
+            Path::new("a.epub"),
+            &enabled(&["xhtml"])
+        ));
+    }
+}
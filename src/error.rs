@@ -0,0 +1,100 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::query::QueryError;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong, with enough context to tell the user what
+/// failed. Every variant exits the process with its own code, see
+/// [`Error::exit_code`].
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid command line arguments
+    Usage(String),
+    /// Reading or writing a file failed
+    Io { context: String, source: io::Error },
+    /// Some files could not be read or parsed while indexing
+    Parse { failed: usize },
+    /// There is no index file at the path
+    IndexMissing(PathBuf),
+    /// The index file exists but can't be decoded
+    IndexFormat { path: PathBuf, reason: String },
+    /// The HTTP server couldn't start or answer a request
+    Http { context: String, reason: String },
+    /// The query can't be parsed
+    Query(QueryError),
+    /// The filesystem watcher failed
+    Watch { path: PathBuf, reason: String },
+}
+
+impl Error {
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        Self::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Error opening or reading the index at `path`, telling a missing
+    /// index apart from one that can't be read
+    pub fn index_io(path: &Path, source: io::Error) -> Self {
+        if source.kind() == io::ErrorKind::NotFound {
+            return Self::IndexMissing(path.to_path_buf());
+        }
+        Self::io(format!("could not read index {}", path.display()), source)
+    }
+
+    pub fn index_format(path: &Path, reason: impl ToString) -> Self {
+        Self::IndexFormat {
+            path: path.to_path_buf(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Usage(_) => 2,
+            Self::Io { .. } => 3,
+            Self::Parse { .. } => 4,
+            Self::IndexMissing(_) => 5,
+            Self::IndexFormat { .. } => 6,
+            Self::Http { .. } => 7,
+            Self::Query(_) => 8,
+            Self::Watch { .. } => 9,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usage(message) => write!(f, "{message}"),
+            Self::Io { context, source } => write!(f, "{context}; {source}"),
+            Self::Parse { failed } => write!(f, "could not index {failed} file(s)"),
+            Self::IndexMissing(path) => write!(f, "no index at {}", path.display()),
+            Self::IndexFormat { path, reason } => {
+                write!(f, "invalid index {}; {reason}", path.display())
+            }
+            Self::Http { context, reason } => write!(f, "{context}; {reason}"),
+            Self::Query(err) => write!(
+                f,
+                "invalid query at character {}; {}",
+                err.position, err.message
+            ),
+            Self::Watch { path, reason } => {
+                write!(f, "could not watch {}; {reason}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
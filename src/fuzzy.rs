@@ -0,0 +1,245 @@
+//! Terms within a few edits of a query term. The Levenshtein automaton of
+//! the term is run over the sorted term dictionary, and whole ranges of
+//! terms sharing a prefix the automaton rejects are skipped.
+
+use crate::error::*;
+use crate::model::*;
+
+/// Most edits allowed in a fuzzy term
+pub const MAX_EDITS: usize = 2;
+
+/// Accepts the strings within `max_edits` insertions, deletions or
+/// substitutions of `term`. A state is the row of edit distances between
+/// the input read so far and every prefix of `term`.
+pub struct LevenshteinAutomaton {
+    term: Vec<char>,
+    max_edits: usize,
+}
+
+impl LevenshteinAutomaton {
+    pub fn new(term: &str, max_edits: usize) -> Self {
+        Self {
+            term: term.chars().collect(),
+            max_edits,
+        }
+    }
+
+    pub fn start(&self) -> Vec<usize> {
+        (0..=self.term.len()).collect()
+    }
+
+    pub fn step(&self, state: &[usize], c: char) -> Vec<usize> {
+        let mut next = Vec::with_capacity(state.len());
+        next.push(state[0] + 1);
+        for (i, expected) in self.term.iter().enumerate() {
+            let substitution = state[i] + usize::from(*expected != c);
+            next.push(substitution.min(state[i + 1] + 1).min(next[i] + 1));
+        }
+        next
+    }
+
+    /// Edit distance of the input read so far, if it's accepted
+    pub fn distance(&self, state: &[usize]) -> Option<usize> {
+        let distance = state[self.term.len()];
+        (distance <= self.max_edits).then_some(distance)
+    }
+
+    /// Whether reading more input can still lead to a match
+    pub fn can_match(&self, state: &[usize]) -> bool {
+        state.iter().any(|distance| *distance <= self.max_edits)
+    }
+}
+
+/// Smallest string greater than every string starting with `prefix`
+fn prefix_successor(prefix: &[char]) -> Option<String> {
+    let (last, init) = prefix.split_last()?;
+    let next = (*last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+    match next {
+        Some(next) => Some(init.iter().chain([&next]).collect()),
+        None => prefix_successor(init),
+    }
+}
+
+/// Indexed terms within `max_edits` of `term`, closest first, at most
+/// `limit` of them
+pub fn fuzzy_terms(
+    index: &dyn IndexReader,
+    term: &str,
+    max_edits: usize,
+    limit: usize,
+) -> Result<Vec<(String, usize)>> {
+    let automaton = LevenshteinAutomaton::new(term, max_edits);
+    let mut matches = Vec::new();
+    let mut from = String::new();
+    loop {
+        // States after every prefix of the previous term, shared with the
+        // next one as far as their prefixes match
+        let mut states = vec![automaton.start()];
+        let mut previous = Vec::<char>::new();
+        let mut skip_to = None;
+        index.visit_terms_from(&from, &mut |candidate| {
+            let candidate = candidate.chars().collect::<Vec<_>>();
+            let common = previous
+                .iter()
+                .zip(&candidate)
+                .take_while(|(a, b)| a == b)
+                .count();
+            states.truncate(common + 1);
+            for (i, c) in candidate.iter().enumerate().skip(common) {
+                let state = automaton.step(&states[i], *c);
+                if !automaton.can_match(&state) {
+                    skip_to = prefix_successor(&candidate[..=i]);
+                    return false;
+                }
+                states.push(state);
+            }
+            if let Some(distance) = automaton.distance(&states[candidate.len()]) {
+                matches.push((candidate.iter().collect(), distance));
+            }
+            previous = candidate;
+            true
+        })?;
+        match skip_to.take() {
+            Some(next) => from = next,
+            None => break,
+        }
+    }
+    matches.sort_by_key(|(_, distance)| *distance);
+    matches.truncate(limit);
+    Ok(matches)
+}
+
+/// Edits allowed when a term is looked up fuzzily without saying how many,
+/// the longer the term the more
+pub fn auto_edits(term: &str) -> usize {
+    match term.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::cell::Cell;
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::scorer::*;
+
+    fn distance(term: &str, candidate: &str, max_edits: usize) -> Option<usize> {
+        let automaton = LevenshteinAutomaton::new(term, max_edits);
+        let mut state = automaton.start();
+        for c in candidate.chars() {
+            state = automaton.step(&state, c);
+        }
+        automaton.distance(&state)
+    }
+
+    #[test]
+    fn distances_up_to_the_limit() {
+        assert_eq!(distance("memory", "memory", 2), Some(0));
+        assert_eq!(distance("memory", "memry", 2), Some(1));
+        assert_eq!(distance("memory", "memroy", 2), Some(2));
+        assert_eq!(distance("memory", "memoryyy", 2), Some(2));
+        assert_eq!(distance("memory", "mem", 2), None);
+        assert_eq!(distance("memory", "memroy", 1), None);
+        assert_eq!(distance("", "ab", 2), Some(2));
+        assert_eq!(distance("ab", "", 1), None);
+    }
+
+    #[test]
+    fn rejects_once_too_far_off() {
+        let automaton = LevenshteinAutomaton::new("abc", 1);
+        let mut state = automaton.start();
+        for c in "xy".chars() {
+            state = automaton.step(&state, c);
+        }
+        assert!(!automaton.can_match(&state));
+    }
+
+    #[test]
+    fn successor_of_a_prefix() {
+        let chars = |text: &str| text.chars().collect::<Vec<_>>();
+        assert_eq!(prefix_successor(&chars("ab")), Some("ac".to_string()));
+        assert_eq!(
+            prefix_successor(&chars("a\u{d7ff}")),
+            Some("a\u{e000}".to_string())
+        );
+        assert_eq!(
+            prefix_successor(&chars("a\u{10ffff}")),
+            Some("b".to_string())
+        );
+        assert_eq!(prefix_successor(&chars("\u{10ffff}")), None);
+        assert_eq!(prefix_successor(&[]), None);
+    }
+
+    /// Model that counts the terms it's asked to visit
+    struct CountingIndex {
+        model: Model,
+        visited: Cell<usize>,
+    }
+
+    impl IndexReader for CountingIndex {
+        fn corpus_stats(&self) -> CorpusStats {
+            self.model.corpus_stats()
+        }
+
+        fn doc_stats(&self, doc: DocId) -> Result<DocStats> {
+            self.model.doc_stats(doc)
+        }
+
+        fn doc_location(&self, doc: DocId) -> Result<(PathBuf, Option<String>)> {
+            self.model.doc_location(doc)
+        }
+
+        fn file_docs(&self, path: &Path) -> Result<Vec<DocId>> {
+            self.model.file_docs(path)
+        }
+
+        fn postings(&self, term: &str) -> Result<Cow<'_, [Posting]>> {
+            self.model.postings(term)
+        }
+
+        fn surface_forms(&self, term: &str) -> Result<Vec<(String, usize)>> {
+            self.model.surface_forms(term)
+        }
+
+        fn visit_terms_from(&self, from: &str, visit: &mut dyn FnMut(&str) -> bool) -> Result<()> {
+            self.model.visit_terms_from(from, &mut |term| {
+                self.visited.set(self.visited.get() + 1);
+                visit(term)
+            })
+        }
+    }
+
+    #[test]
+    fn skips_terms_that_cannot_match() {
+        let mut model = Model::default();
+        let mut terms = vec!["memori", "memo", "memory", "mimori", "safeti"];
+        // Plenty of terms the automaton rejects after two characters
+        let filler = (0..100).map(|i| format!("mz{i:03}")).collect::<Vec<_>>();
+        terms.extend(filler.iter().map(String::as_str));
+        for term in &terms {
+            model.postings.insert(term.to_string(), Vec::new());
+        }
+        let index = CountingIndex {
+            model,
+            visited: Cell::new(0),
+        };
+
+        let found = fuzzy_terms(&index, "memori", 1, 10).unwrap();
+        let expected = vec![
+            ("memori".to_string(), 0),
+            ("memory".to_string(), 1),
+            ("mimori".to_string(), 1),
+        ];
+        assert_eq!(found, expected);
+        assert!(index.visited.get() < 20, "visited {}", index.visited.get());
+
+        let found = fuzzy_terms(&index, "memori", 2, 2).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0], ("memori".to_string(), 0));
+    }
+}
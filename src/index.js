@@ -3,18 +3,45 @@ console.log("Querying api/search");
 async function search(prompt) {
 	const results = document.getElementById("results")
 	results.innerHTML = "";
-	const response = await fetch("/api/search", {
+	const response = await fetch("/api/search?snippets=true", {
 		method: 'POST',
 		headers: { 'Content-Type': 'text/plain' },
 		body: prompt,
 	});
 	const json = await response.json();
 	results.innerHTML = "";
-	for ([path, rank] of json) {
-		let item = document.createElement("span");
-		item.appendChild(document.createTextNode(path));
-		item.appendChild(document.createElement("br"));
+	if (!response.ok) {
+		// Half typed queries like `rust AND` don't parse yet
+		results.appendChild(document.createTextNode(json.message));
+		return;
+	}
+	if (json.suggestion !== null) {
+		let suggestion = document.createElement("a");
+		suggestion.href = "#";
+		suggestion.appendChild(document.createTextNode(json.suggestion));
+		suggestion.addEventListener("click", (event) => {
+			event.preventDefault();
+			inputElement.value = json.suggestion;
+			currentSearch.then(() => search(json.suggestion));
+		});
+		results.appendChild(document.createTextNode("Did you mean: "));
+		results.appendChild(suggestion);
+		results.appendChild(document.createElement("br"));
+	}
+	for (const hit of json.results) {
+		// Keep fragments like #page=3 out of the query so the viewer gets them
+		const [file, fragment] = hit.path.split("#");
+		let item = document.createElement("a");
+		item.href = "/document?path=" + encodeURIComponent(file) + (fragment ? "#" + fragment : "");
+		item.appendChild(document.createTextNode(hit.path));
 		results.appendChild(item);
+		results.appendChild(document.createElement("br"));
+		for (const snippet of hit.snippets) {
+			// Escaped by the server, only the <mark> tags are markup
+			let text = document.createElement("p");
+			text.innerHTML = snippet;
+			results.appendChild(text);
+		}
 	}
 }
 
@@ -0,0 +1,561 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
+
+use crate::archive::*;
+use crate::lexer::*;
+use crate::model::*;
+use crate::parser::*;
+
+pub struct IndexOptions {
+    /// Number of threads reading and tokenizing documents
+    pub jobs: usize,
+    pub parsers: Parsers,
+    /// When not empty, only files matching one of these globs are indexed
+    pub include: Vec<String>,
+    /// Files and folders matching any of these globs are skipped
+    pub exclude: Vec<String>,
+    /// Walk into hidden files and folders
+    pub hidden: bool,
+    /// Respect `.gitignore` and `.ignore` files
+    pub ignore_files: bool,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        Self {
+            jobs: thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+            parsers: Parsers::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            hidden: false,
+            ignore_files: true,
+        }
+    }
+}
+
+impl IndexOptions {
+    /// Include and exclude globs, anchored at `root`
+    pub fn overrides(&self, root: &Path) -> Result<Override, String> {
+        let mut builder = OverrideBuilder::new(root);
+        for glob in &self.include {
+            builder
+                .add(glob)
+                .map_err(|err| format!("invalid include glob {glob}; {err}"))?;
+        }
+        for glob in &self.exclude {
+            builder
+                .add(&format!("!{glob}"))
+                .map_err(|err| format!("invalid exclude glob {glob}; {err}"))?;
+        }
+        builder.build().map_err(|err| err.to_string())
+    }
+
+    fn walker(&self, root: &Path, path: &Path) -> WalkBuilder {
+        let mut walker = WalkBuilder::new(path);
+        walker
+            .hidden(!self.hidden)
+            .ignore(self.ignore_files)
+            .git_ignore(self.ignore_files)
+            .git_exclude(self.ignore_files)
+            .git_global(false)
+            .require_git(false)
+            .overrides(self.overrides(root).expect("globs are validated up front"));
+        walker
+    }
+}
+
+/// Why a file or folder could not be indexed
+#[derive(Debug)]
+pub enum IndexError {
+    /// The file couldn't be read from disk
+    Read(io::Error),
+    /// The file was read but its content couldn't be parsed
+    Parse(io::Error),
+    /// The folder couldn't be walked, like when permissions are missing
+    Walk(ignore::Error),
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(err) => write!(f, "could not read file; {err}"),
+            Self::Parse(err) => write!(f, "could not parse file; {err}"),
+            Self::Walk(err) => write!(f, "could not walk folder; {err}"),
+        }
+    }
+}
+
+impl std::error::Error for IndexError {}
+
+/// A file that was skipped while indexing and the reason why
+pub struct Failure {
+    pub path: PathBuf,
+    pub error: IndexError,
+}
+
+impl Failure {
+    /// Failure to walk `path`, located at the entry the walker choked on
+    fn walk(path: &Path, err: ignore::Error) -> Self {
+        match err {
+            ignore::Error::WithPath { path, err } => Self {
+                path,
+                error: IndexError::Walk(*err),
+            },
+            ignore::Error::WithDepth { err, .. } => Self::walk(path, *err),
+            err => Self {
+                path: path.to_path_buf(),
+                error: IndexError::Walk(err),
+            },
+        }
+    }
+}
+
+/// Print every failure of an indexing run, one per line
+pub fn report_failures(failures: &[Failure]) {
+    if failures.is_empty() {
+        return;
+    }
+    eprintln!(
+        "Skipped {} file(s) that could not be indexed:",
+        failures.len()
+    );
+    for failure in failures {
+        eprintln!("    {}: {}", failure.path.display(), failure.error);
+    }
+}
+
+fn is_indexable(file_path: &Path, parsers: &Parsers) -> bool {
+    parsers.for_path(file_path).is_some() || parsers.opens_archive(file_path)
+}
+
+/// Files to index under `path`, which is either a folder or a single file,
+/// skipping whatever the ignore files and globs rule out
+fn collect_files(
+    root: &Path,
+    path: &Path,
+    options: &IndexOptions,
+    files: &mut Vec<PathBuf>,
+    failures: &mut Vec<Failure>,
+) {
+    // The walker never filters the path it starts from, so paths under the
+    // root are reached from it, only walking into the folders on the way
+    let walker = if path != root && path.starts_with(root) {
+        let target = path.to_path_buf();
+        options
+            .walker(root, root)
+            .filter_entry(move |entry| {
+                target.starts_with(entry.path()) || entry.path().starts_with(&target)
+            })
+            .build()
+    } else {
+        options.walker(root, path).build()
+    };
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                failures.push(Failure::walk(path, err));
+                continue;
+            }
+        };
+        let is_file = entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_file());
+        if !is_file || !is_indexable(entry.path(), &options.parsers) {
+            continue;
+        }
+        files.push(entry.into_path());
+    }
+}
+
+fn compute_term_positions(content: &[char]) -> (TermPositions, SurfaceForms) {
+    let mut terms = TermPositions::new();
+    let mut forms = SurfaceForms::new();
+    for token in Lexer::new(content).tokens() {
+        *forms
+            .entry(token.term.clone())
+            .or_default()
+            .entry(token.text.to_ascii_lowercase())
+            .or_default() += 1;
+        terms.entry(token.term).or_default().push(token.position);
+    }
+    (terms, forms)
+}
+
+/// Run `work` over `items` on `jobs` threads, handing every result to
+/// `merge` on the calling thread as soon as it is ready
+fn parallel_map<T, R, W, M>(items: Vec<T>, jobs: usize, work: W, mut merge: M)
+where
+    T: Send,
+    R: Send,
+    W: Fn(T) -> R + Sync,
+    M: FnMut(R),
+{
+    let queue = Mutex::new(items.into_iter());
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            let sender = sender.clone();
+            let (queue, work) = (&queue, &work);
+            scope.spawn(move || loop {
+                let Some(item) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                if sender.send(work(item)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        for result in receiver {
+            merge(result);
+        }
+    });
+}
+
+/// A file that needs to be read, with the documents it replaces if any
+struct IndexJob {
+    path: PathBuf,
+    metadata: fs::Metadata,
+    existing: Option<(Vec<DocId>, FileStamp)>,
+}
+
+enum JobResult {
+    /// Content hash matches the indexed documents, only the stamp changed
+    Unchanged(Vec<DocId>, FileStamp),
+    Indexed {
+        path: PathBuf,
+        replaces: Option<Vec<DocId>>,
+        stamp: FileStamp,
+        parts: Vec<(Option<String>, (TermPositions, SurfaceForms))>,
+        /// Entries of an archive that couldn't be parsed
+        failures: Vec<Failure>,
+    },
+    Failed(Failure),
+}
+
+/// Text of every document in the file, archives included. Archive entries
+/// that can't be parsed are skipped and added to `failures`.
+fn parse_file(
+    path: &Path,
+    bytes: &[u8],
+    parsers: &Parsers,
+    failures: &mut Vec<Failure>,
+) -> io::Result<Vec<(Option<String>, String)>> {
+    let Some(kind) = ArchiveKind::of(path) else {
+        let parser = parsers
+            .for_path(path)
+            .expect("only files with a parser are queued");
+        return parser.parse_parts(bytes);
+    };
+
+    let mut parts = Vec::new();
+    let wanted = |name: &str| parsers.for_path(Path::new(name)).is_some();
+    kind.for_each_entry(bytes, wanted, |name, entry| {
+        let parser = parsers
+            .for_path(Path::new(name))
+            .expect("only entries with a parser are visited");
+        match parser.parse_parts(&entry) {
+            Ok(entry_parts) => {
+                for (part, text) in entry_parts {
+                    let part = format!("{ENTRY_SEPARATOR}{name}{}", part.unwrap_or_default());
+                    parts.push((Some(part), text));
+                }
+            }
+            Err(err) => failures.push(Failure {
+                path: full_path(path, Some(&format!("{ENTRY_SEPARATOR}{name}"))),
+                error: IndexError::Parse(err),
+            }),
+        }
+    })?;
+    Ok(parts)
+}
+
+fn run_job(job: IndexJob, parsers: &Parsers) -> JobResult {
+    let bytes = match fs::read(&job.path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            return JobResult::Failed(Failure {
+                path: job.path,
+                error: IndexError::Read(err),
+            })
+        }
+    };
+    let stamp = FileStamp::new(&job.metadata, &bytes);
+    let replaces = match job.existing {
+        Some((docs, old)) if old.hash == stamp.hash => return JobResult::Unchanged(docs, stamp),
+        Some((docs, _)) => Some(docs),
+        None => None,
+    };
+
+    let mut failures = Vec::new();
+    let parts = match parse_file(&job.path, &bytes, parsers, &mut failures) {
+        Ok(parts) => parts,
+        Err(err) => {
+            return JobResult::Failed(Failure {
+                path: job.path,
+                error: IndexError::Parse(err),
+            })
+        }
+    };
+
+    println!("Indexing... {:?}", job.path);
+
+    let parts = parts
+        .into_iter()
+        .map(|(part, text)| {
+            (
+                part,
+                compute_term_positions(&text.chars().collect::<Vec<_>>()),
+            )
+        })
+        .collect();
+    JobResult::Indexed {
+        path: job.path,
+        replaces,
+        stamp,
+        parts,
+        failures,
+    }
+}
+
+pub fn add_folder_to_model(
+    dir_path: &Path,
+    model: &mut Model,
+    options: &IndexOptions,
+) -> UpdateStats {
+    update_folder_in_model(dir_path, model, options)
+}
+
+#[derive(Default)]
+pub struct UpdateStats {
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    /// Files that were skipped, the rest of the folder is indexed anyway
+    pub failures: Vec<Failure>,
+}
+
+/// Bring the documents under `dir_path` up to date: files whose mtime and
+/// size (or, failing that, content hash) are unchanged are kept as is,
+/// changed and new files are re-indexed and deleted files are dropped
+pub fn update_folder_in_model(
+    dir_path: &Path,
+    model: &mut Model,
+    options: &IndexOptions,
+) -> UpdateStats {
+    update_paths_in_model(dir_path, &[dir_path.to_path_buf()], model, options)
+}
+
+/// Same as [`update_folder_in_model`] for several paths under `root`, each
+/// of which may be a folder, a single file or a path that no longer exists
+pub fn update_paths_in_model(
+    root: &Path,
+    paths: &[PathBuf],
+    model: &mut Model,
+    options: &IndexOptions,
+) -> UpdateStats {
+    let UpdatePlan {
+        jobs,
+        deleted,
+        stats,
+    } = plan_update(root, paths, model, options);
+    // Documents are added as soon as they are parsed, so only a few are
+    // held in memory at a time
+    let mut changes = Changes::new(stats);
+    let parse = |job| run_job(job, &options.parsers);
+    parallel_map(jobs, options.jobs, parse, |result| {
+        changes.merge(model, result)
+    });
+    changes.finish(model, deleted)
+}
+
+/// Files to read and documents to drop to bring paths up to date, as found
+/// in the model when the plan was made
+pub struct UpdatePlan {
+    jobs: Vec<IndexJob>,
+    /// Documents of the files that no longer exist
+    deleted: Vec<(PathBuf, Vec<DocId>)>,
+    stats: UpdateStats,
+}
+
+/// Planned files read and tokenized, ready to be applied to the model
+pub struct ParsedUpdate {
+    results: Vec<JobResult>,
+    deleted: Vec<(PathBuf, Vec<DocId>)>,
+    stats: UpdateStats,
+}
+
+/// Work out what [`update_paths_in_model`] has to do without changing the
+/// model, so it only needs to be read
+pub fn plan_update(
+    root: &Path,
+    paths: &[PathBuf],
+    model: &Model,
+    options: &IndexOptions,
+) -> UpdatePlan {
+    let mut stats = UpdateStats::default();
+    let mut indexed = HashMap::<PathBuf, Vec<DocId>>::new();
+    for (doc, document) in model.docs.iter().enumerate() {
+        indexed.entry(document.path.clone()).or_default().push(doc);
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        if path.exists() {
+            collect_files(root, path, options, &mut files, &mut stats.failures);
+        }
+    }
+    // A file inside a folder that is also listed shows up twice
+    files.sort();
+    files.dedup();
+    let mut seen = HashSet::<DocId>::new();
+    let mut jobs = Vec::new();
+    for file_path in files {
+        // Documents are found and served by their path as text
+        if file_path.to_str().is_none() {
+            stats.failures.push(Failure {
+                path: file_path,
+                error: IndexError::Read(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "path is not valid UTF-8",
+                )),
+            });
+            continue;
+        }
+        // Files may disappear while a folder is being watched, skip them
+        let metadata = match fs::metadata(&file_path) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                stats.failures.push(Failure {
+                    path: file_path,
+                    error: IndexError::Read(err),
+                });
+                continue;
+            }
+        };
+        let existing = indexed.get(&file_path);
+        if let Some(docs) = existing {
+            seen.extend(docs.iter().copied());
+            let old = model.docs[docs[0]].stamp;
+            let new = FileStamp::from_metadata(&metadata);
+            if old.mtime == new.mtime && old.size == new.size {
+                stats.unchanged += 1;
+                continue;
+            }
+        }
+        jobs.push(IndexJob {
+            existing: existing.map(|docs| (docs.clone(), model.docs[docs[0]].stamp)),
+            path: file_path,
+            metadata,
+        });
+    }
+
+    let deleted = indexed
+        .into_iter()
+        .filter(|(path, docs)| {
+            let under_paths = paths.iter().any(|root| path.starts_with(root));
+            under_paths && !seen.contains(&docs[0])
+        })
+        .collect();
+    UpdatePlan {
+        jobs,
+        deleted,
+        stats,
+    }
+}
+
+impl UpdatePlan {
+    /// Read and tokenize the planned files without touching the model
+    pub fn parse(self, options: &IndexOptions) -> ParsedUpdate {
+        let mut results = Vec::with_capacity(self.jobs.len());
+        let parse = |job| run_job(job, &options.parsers);
+        parallel_map(self.jobs, options.jobs, parse, |result| {
+            results.push(result)
+        });
+        ParsedUpdate {
+            results,
+            deleted: self.deleted,
+            stats: self.stats,
+        }
+    }
+}
+
+impl ParsedUpdate {
+    /// Bring the model up to date. Its documents must not have changed
+    /// since the update was planned.
+    pub fn apply(self, model: &mut Model) -> UpdateStats {
+        let mut changes = Changes::new(self.stats);
+        for result in self.results {
+            changes.merge(model, result);
+        }
+        changes.finish(model, self.deleted)
+    }
+}
+
+/// Documents replaced so far by an update being applied
+struct Changes {
+    stale: HashSet<DocId>,
+    stats: UpdateStats,
+}
+
+impl Changes {
+    fn new(stats: UpdateStats) -> Self {
+        Self {
+            stale: HashSet::new(),
+            stats,
+        }
+    }
+
+    fn merge(&mut self, model: &mut Model, result: JobResult) {
+        let stats = &mut self.stats;
+        match result {
+            JobResult::Unchanged(docs, stamp) => {
+                for doc in docs {
+                    model.docs[doc].stamp = stamp;
+                }
+                stats.unchanged += 1;
+            }
+            JobResult::Indexed {
+                path,
+                replaces,
+                stamp,
+                parts,
+                failures,
+            } => {
+                if let Some(docs) = replaces {
+                    self.stale.extend(docs);
+                    stats.changed += 1;
+                } else {
+                    stats.added += 1;
+                }
+                for (part, (terms, forms)) in parts {
+                    model.add_document(path.clone(), part, stamp, terms, forms);
+                }
+                stats.failures.extend(failures);
+            }
+            JobResult::Failed(failure) => stats.failures.push(failure),
+        }
+    }
+
+    /// Drop the replaced documents and those of `deleted` files
+    fn finish(mut self, model: &mut Model, deleted: Vec<(PathBuf, Vec<DocId>)>) -> UpdateStats {
+        for (path, docs) in deleted {
+            println!("Removing... {path:?}");
+            self.stale.extend(docs);
+            self.stats.removed += 1;
+        }
+        model.remove_documents(&self.stale);
+        self.stats.failures.sort_by(|a, b| a.path.cmp(&b.path));
+        self.stats
+    }
+}
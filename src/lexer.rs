@@ -1,22 +1,47 @@
+/// A term and where it was read from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    /// Lowercased and stemmed text, as it is indexed
+    pub term: String,
+    /// Text as it was read
+    pub text: String,
+    /// Offset in characters of the first character of the text
+    pub start: usize,
+    /// Offset in characters right after the last character of the text
+    pub end: usize,
+    /// Number of tokens before this one
+    pub position: usize,
+}
+
 #[derive(Debug)]
 pub struct Lexer<'a> {
     content: &'a [char],
+    /// Characters chopped off so far
+    offset: usize,
+    /// Tokens read so far
+    position: usize,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(content: &'a [char]) -> Self {
-        Self { content }
+        Self {
+            content,
+            offset: 0,
+            position: 0,
+        }
     }
 
     fn trim_left(&mut self) {
-        while self.content.len() > 0 && self.content[0].is_whitespace() {
+        while !self.content.is_empty() && self.content[0].is_whitespace() {
             self.content = &self.content[1..];
+            self.offset += 1;
         }
     }
 
     fn chop(&mut self, n: usize) -> &'a [char] {
         let token = &self.content[0..n];
         self.content = &self.content[n..];
+        self.offset += n;
         token
     }
 
@@ -31,30 +56,46 @@ impl<'a> Lexer<'a> {
         self.chop(n)
     }
 
-    fn next_token(&mut self) -> Option<String> {
+    pub fn next_token(&mut self) -> Option<Token> {
         self.trim_left();
-        if self.content.len() == 0 {
+        if self.content.is_empty() {
             return None;
         }
 
-        if self.content[0].is_numeric() {
-            return Some(self.chop_while(|x| x.is_numeric()).iter().collect());
-        }
-
-        if self.content[0].is_alphabetic() {
-            let term = self
-                .chop_while(|x| x.is_alphanumeric())
+        let start = self.offset;
+        let (text, term) = if self.content[0].is_numeric() {
+            let text = self.chop_while(|x| x.is_numeric());
+            (text, text.iter().collect())
+        } else if self.content[0].is_alphabetic() {
+            let text = self.chop_while(|x| x.is_alphanumeric());
+            let term = text
                 .iter()
                 .map(|x| x.to_ascii_lowercase())
                 .collect::<String>();
             // NOTE: Automatically stem the tokens
             let mut env = crate::snowball::SnowballEnv::create(&term);
             crate::snowball::algorithms::english_stemmer::stem(&mut env);
-            let term = env.get_current().to_string();
-            return Some(term);
-        }
+            (text, env.get_current().to_string())
+        } else {
+            let text = self.chop(1);
+            (text, text.iter().collect())
+        };
 
-        Some(self.chop(1).iter().collect())
+        let position = self.position;
+        self.position += 1;
+        Some(Token {
+            term,
+            text: text.iter().collect(),
+            start,
+            end: self.offset,
+            position,
+        })
+    }
+
+    /// Tokens with everything known about them, where iterating the lexer
+    /// only yields the terms
+    pub fn tokens(mut self) -> impl Iterator<Item = Token> + 'a {
+        std::iter::from_fn(move || self.next_token())
     }
 }
 
@@ -62,6 +103,88 @@ impl<'a> Iterator for Lexer<'a> {
     type Item = String;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.next_token()
+        self.next_token().map(|token| token.term)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text, offsets and position of every token of `text`
+    fn tokens(text: &str) -> Vec<(String, usize, usize, usize)> {
+        let chars = text.chars().collect::<Vec<_>>();
+        Lexer::new(&chars)
+            .tokens()
+            .map(|token| (token.text, token.start, token.end, token.position))
+            .collect()
+    }
+
+    fn token(
+        text: &str,
+        start: usize,
+        end: usize,
+        position: usize,
+    ) -> (String, usize, usize, usize) {
+        (text.to_string(), start, end, position)
+    }
+
+    #[test]
+    fn whitespace_is_skipped_but_counted() {
+        assert_eq!(tokens(""), []);
+        assert_eq!(tokens(" \t\n "), []);
+        assert_eq!(
+            tokens("  foo \n\n  bar "),
+            [token("foo", 2, 5, 0), token("bar", 10, 13, 1)]
+        );
+    }
+
+    #[test]
+    fn punctuation_is_a_token_per_character() {
+        assert_eq!(
+            tokens("a,b..."),
+            [
+                token("a", 0, 1, 0),
+                token(",", 1, 2, 1),
+                token("b", 2, 3, 2),
+                token(".", 3, 4, 3),
+                token(".", 4, 5, 4),
+                token(".", 5, 6, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_end_where_letters_start() {
+        assert_eq!(
+            tokens("2024abc x86 3.14"),
+            [
+                token("2024", 0, 4, 0),
+                token("abc", 4, 7, 1),
+                token("x86", 8, 11, 2),
+                token("3", 12, 13, 3),
+                token(".", 13, 14, 4),
+                token("14", 14, 16, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn offsets_count_characters_not_bytes() {
+        assert_eq!(
+            tokens("ünïcödé — naïve"),
+            [
+                token("ünïcödé", 0, 7, 0),
+                token("—", 8, 9, 1),
+                token("naïve", 10, 15, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn terms_are_lowercased_and_stemmed() {
+        let chars = "Running CATS".chars().collect::<Vec<_>>();
+        let terms = Lexer::new(&chars).collect::<Vec<_>>();
+        assert_eq!(terms, ["run", "cat"]);
     }
 }
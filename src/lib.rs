@@ -0,0 +1,19 @@
+//! Full text search over a folder of documents: indexing, ranked queries and
+//! the HTTP server, with the ranking functions pluggable through
+//! [`scorer::Scorers`].
+
+pub mod archive;
+pub mod error;
+pub mod fuzzy;
+pub mod indexer;
+pub mod lexer;
+pub mod model;
+pub mod parser;
+pub mod query;
+pub mod scorer;
+pub mod server;
+pub mod snippet;
+pub mod snowball;
+pub mod storage;
+pub mod suggest;
+pub mod watch;
@@ -1,72 +1,401 @@
 use std::env;
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::{Arc, RwLock};
+use std::thread;
 
-// Modules
-mod lexer;
-mod model;
-mod server;
-mod snowball;
-
-use model::*;
-use server::*;
+use search_engine::error::*;
+use search_engine::indexer::*;
+use search_engine::model::*;
+use search_engine::scorer::*;
+use search_engine::server::*;
+use search_engine::suggest::*;
+use search_engine::watch::*;
 
 fn usage(program: &str) {
     eprintln!("Usage: {program} [SubCommand] [Options]");
     eprintln!("Subcommands:");
-    eprintln!("    index  <folder>        index the <folder> and save the files into a index.json");
     eprintln!(
-        "    search <index-file>    check how many documents were indexed into the index file"
+        "    index  <folder>                   index the <folder> and save the index into a file"
+    );
+    eprintln!("        -o, --output <file>           where to save the index (default: index.bin or index.json)");
+    eprintln!("        --format <bin|json>           index file format (default: bin, or the format of the updated index)");
+    eprintln!("        --update                      re-index only new and changed files of an existing index");
+    eprintln!("        -j, --jobs <count>            number of indexing threads (default: number of CPUs)");
+    eprintln!("        --ext <ext,...>               only index files with these extensions (default: all supported)");
+    eprintln!("                                      archives are only opened if listed too, EPUBs also if xhtml or html is");
+    eprintln!(
+        "        --include <glob>              only index files matching the glob, may be repeated"
+    );
+    eprintln!("        --exclude <glob>              skip files and folders matching the glob, may be repeated");
+    eprintln!("        --hidden                      index hidden files and folders");
+    eprintln!("        --no-ignore                   don't respect .gitignore and .ignore files");
+    eprintln!(
+        "        --strict                      exit with an error if any file could not be indexed"
+    );
+    eprintln!(
+        "    search <index-file> <query...>    search the index and print the ranked documents"
+    );
+    eprintln!(
+        "                                      words in \"double quotes\" only match as a phrase"
+    );
+    eprintln!("                                      +word, -word, AND, OR, NOT and parentheses filter the results");
+    eprintln!("                                      term* and te?m match every indexed term fitting the pattern");
+    eprintln!("                                      term~2 matches indexed terms within 2 edits, term~ picks the edits by length");
+    eprintln!("        -n, --limit <count>           how many results to print (default: 10, 0 means all)");
+    eprintln!("        --format <text|json|tsv>      output format (default: text)");
+    eprintln!("        --ranking <tfidf|bm25|cosine> ranking function (default: tfidf)");
+    eprintln!(
+        "        --k1 <value>                  BM25 term frequency saturation (default: 1.2)"
+    );
+    eprintln!(
+        "        --b <value>                   BM25 document length normalization (default: 0.75)"
+    );
+    eprintln!("        --proximity <weight>          boost for query terms close together, 0 disables (default: 0.5)");
+    eprintln!("        --fuzzy                       match terms missing from the index as if they were term~");
+    eprintln!("    terms  <index-file> <words...>    print the indexed terms of the words and the words each is stemmed from");
+    eprintln!("        --format <text|json>          output format (default: text)");
+    eprintln!("    serve  <index-file> [address]     start local HTTP server");
+    eprintln!(
+        "        --watch <folder>              keep the index in sync with changes to <folder>"
+    );
+    eprintln!("        --ext, --include, --exclude, --hidden, --no-ignore, -j");
+    eprintln!(
+        "                                      choose and read the watched files like index does"
+    );
+    eprintln!("Exit codes:");
+    eprintln!("    2 invalid arguments, 3 I/O error, 4 files failed to index (--strict), 5 index missing,");
+    eprintln!(
+        "    6 invalid index, 7 HTTP error (e.g. address in use), 8 invalid query, 9 watch error"
     );
-    eprintln!("    serve                  start local HTTP server");
 }
 
-fn entry() -> Result<(), ()> {
-    let mut args = env::args();
-    let program = args.next().expect("path to program is provided");
-    let subcommand = args.next().ok_or_else(|| {
-        usage(&program);
-        eprintln!("Error: no subcommand is provided");
-    })?;
+#[derive(Clone, Copy)]
+enum OutputFormat {
+    Text,
+    Json,
+    Tsv,
+}
 
-    match subcommand.as_str() {
-        "index" => {
-            let dir_path = args.next().ok_or_else(|| {
-                usage(&program);
-                eprintln!("Error: no directory provided for indexing");
-            })?;
-            let mut model: Model = Default::default();
-            let dir_path = Path::new(&dir_path);
-            add_folder_to_model(&dir_path, &mut model);
-            match save_model_as_json("index.json", &model) {
-                Err(_) => {
-                    eprintln!("Couldn't save index into index.json file");
-                    return Err(());
+impl OutputFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            "tsv" => Some(Self::Tsv),
+            _ => None,
+        }
+    }
+}
+
+fn print_results(rank: &[(PathBuf, f32)], format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            for (i, (path, score)) in rank.iter().enumerate() {
+                println!("{:>4}. {score:.6}  {}", i + 1, path.display());
+            }
+        }
+        OutputFormat::Tsv => {
+            for (i, (path, score)) in rank.iter().enumerate() {
+                println!("{}\t{score}\t{}", i + 1, path.display());
+            }
+        }
+        OutputFormat::Json => {
+            let json = serde_json::to_string(rank).expect("paths and scores serialize");
+            println!("{json}");
+        }
+    }
+}
+
+fn option_value(option: &str, args: &mut env::Args) -> Result<String> {
+    args.next()
+        .ok_or_else(|| Error::Usage(format!("no value provided for {option}")))
+}
+
+fn parse_float_option(option: &str, args: &mut env::Args) -> Result<f32> {
+    let value = option_value(option, args)?;
+    value
+        .parse()
+        .map_err(|err| Error::Usage(format!("invalid value {value} for {option}; {err}")))
+}
+
+fn search(mut args: env::Args) -> Result<()> {
+    let index_file = args
+        .next()
+        .ok_or_else(|| Error::Usage("no index file provided for searching".to_string()))?;
+
+    let mut limit: usize = 10;
+    let mut format = OutputFormat::Text;
+    let mut ranking_name = String::from(DEFAULT_RANKING);
+    let mut k1 = None;
+    let mut b = None;
+    let mut options = SearchOptions::default();
+    let mut query_words = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-n" | "--limit" => {
+                let value = option_value(&arg, &mut args)?;
+                limit = value
+                    .parse()
+                    .map_err(|err| Error::Usage(format!("invalid result count {value}; {err}")))?;
+            }
+            "--format" => {
+                let value = option_value(&arg, &mut args)?;
+                format = OutputFormat::from_name(&value)
+                    .ok_or_else(|| Error::Usage(format!("unknown output format {value}")))?;
+            }
+            "--ranking" => ranking_name = option_value(&arg, &mut args)?,
+            "--k1" => k1 = Some(parse_float_option(&arg, &mut args)?),
+            "--b" => b = Some(parse_float_option(&arg, &mut args)?),
+            "--proximity" => {
+                let proximity = parse_float_option(&arg, &mut args)?;
+                if proximity < 0.0 {
+                    return Err(Error::Usage(format!(
+                        "negative proximity weight {proximity}"
+                    )));
                 }
-                _ => (),
-            };
+                options.proximity = proximity;
+            }
+            "--fuzzy" => options.fuzzy = true,
+            _ => query_words.push(arg),
+        }
+    }
+    let scorer = Scorers::default()
+        .get(&ranking_name, &ScorerParams { k1, b })
+        .ok_or_else(|| Error::Usage(format!("unknown ranking function {ranking_name}")))?;
+
+    if query_words.is_empty() {
+        return Err(Error::Usage("no query provided for searching".to_string()));
+    }
+
+    let index = open_index(Path::new(&index_file))?;
+    let query = query_words.join(" ").chars().collect::<Vec<_>>();
+    let rank = search_query(&query, index.as_ref(), scorer.as_ref(), &options)?.rank;
+    print_results(&resolve_paths(index.as_ref(), &rank, limit)?, format);
+    if rank.is_empty() {
+        if let Some(suggestion) = suggest_query(&query, index.as_ref())? {
+            // Keep machine readable output clean
+            match format {
+                OutputFormat::Text => println!("did you mean: {suggestion}"),
+                OutputFormat::Json | OutputFormat::Tsv => eprintln!("did you mean: {suggestion}"),
+            }
         }
-        "serve" => {
-            let index_file = args.next().unwrap();
-            let index_file = Path::new(&index_file);
-            let model: Model = load_index(&index_file).unwrap();
+    }
+    Ok(())
+}
 
-            let address = args.next().unwrap_or("127.0.0.1:8000".to_string());
-            start(&address, &model)?;
+fn terms(mut args: env::Args) -> Result<()> {
+    let index_file = args
+        .next()
+        .ok_or_else(|| Error::Usage("no index file provided for inspecting".to_string()))?;
+
+    let mut format = OutputFormat::Text;
+    let mut words = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                let value = option_value(&arg, &mut args)?;
+                format = match OutputFormat::from_name(&value) {
+                    Some(OutputFormat::Tsv) | None => {
+                        return Err(Error::Usage(format!("unknown output format {value}")))
+                    }
+                    Some(format) => format,
+                };
+            }
+            _ => words.push(arg),
         }
-        _ => {
-            usage(&program);
-            eprintln!("Error: Unknown subcommand {subcommand}");
-            return Err(());
+    }
+    if words.is_empty() {
+        return Err(Error::Usage("no words provided for inspecting".to_string()));
+    }
+
+    let index = open_index(Path::new(&index_file))?;
+    let text = words.join(" ").chars().collect::<Vec<_>>();
+    let terms = inspect_terms(index.as_ref(), &text)?;
+    if let OutputFormat::Json = format {
+        println!(
+            "{}",
+            serde_json::to_string(&terms).expect("terms serialize")
+        );
+        return Ok(());
+    }
+    for info in terms {
+        let forms = info
+            .forms
+            .iter()
+            .map(|(form, count)| format!("{form} ({count})"))
+            .collect::<Vec<_>>();
+        println!("{}\tdf {}\t{}", info.term, info.df, forms.join(", "));
+    }
+    Ok(())
+}
+
+fn unknown_option(arg: &str) -> Error {
+    Error::Usage(format!("unknown option {arg}"))
+}
+
+fn unexpected_argument(arg: &str) -> Error {
+    Error::Usage(format!("unexpected argument {arg}"))
+}
+
+/// Apply `arg` if it is one of the options choosing and reading the indexed
+/// files, shared by `index` and `serve --watch`
+fn index_option(arg: &str, args: &mut env::Args, options: &mut IndexOptions) -> Result<bool> {
+    match arg {
+        "--ext" => {
+            let value = option_value(arg, args)?;
+            let extensions = value.split(',').collect::<Vec<_>>();
+            options
+                .parsers
+                .enable_only(&extensions)
+                .map_err(Error::Usage)?;
         }
+        "--include" => options.include.push(option_value(arg, args)?),
+        "--exclude" => options.exclude.push(option_value(arg, args)?),
+        "--hidden" => options.hidden = true,
+        "--no-ignore" => options.ignore_files = false,
+        "-j" | "--jobs" => {
+            let value = option_value(arg, args)?;
+            options.jobs = value
+                .parse()
+                .map_err(|err| Error::Usage(format!("invalid thread count {value}; {err}")))?;
+        }
+        _ => return Ok(false),
     }
+    Ok(true)
+}
 
+fn index(mut args: env::Args) -> Result<()> {
+    let mut dir_path = None;
+    let mut output = None;
+    let mut format = None;
+    let mut update = false;
+    let mut strict = false;
+    let mut options = IndexOptions::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(option_value(&arg, &mut args)?),
+            "--format" => {
+                let value = option_value(&arg, &mut args)?;
+                format = Some(
+                    IndexFormat::from_name(&value)
+                        .ok_or_else(|| Error::Usage(format!("unknown index format {value}")))?,
+                );
+            }
+            "--update" => update = true,
+            "--strict" => strict = true,
+            _ if index_option(&arg, &mut args, &mut options)? => {}
+            _ if arg.starts_with('-') => return Err(unknown_option(&arg)),
+            _ if dir_path.is_none() => dir_path = Some(arg),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let dir_path =
+        dir_path.ok_or_else(|| Error::Usage("no directory provided for indexing".to_string()))?;
+    let dir_path = Path::new(&dir_path);
+    // Indexing a missing folder would save an empty index over the output
+    fs::metadata(dir_path)
+        .map_err(|err| Error::io(format!("could not index {}", dir_path.display()), err))?;
+    options.overrides(dir_path).map_err(Error::Usage)?;
+    let output =
+        output.unwrap_or_else(|| format.unwrap_or_default().default_file_name().to_string());
+    let output = Path::new(&output);
+
+    let existing_format = IndexFormat::of_file(output).filter(|_| update);
+    let format = format.or(existing_format).unwrap_or_default();
+    let mut model: Model = Default::default();
+    let stats = if existing_format.is_some() {
+        model = load_index(output)?;
+        let stats = update_folder_in_model(dir_path, &mut model, &options);
+        println!(
+            "Updated index: {} added, {} changed, {} removed, {} unchanged",
+            stats.added, stats.changed, stats.removed, stats.unchanged
+        );
+        stats
+    } else {
+        add_folder_to_model(dir_path, &mut model, &options)
+    };
+    // Files that failed are left out, everything else is still saved
+    save_index(output, &model, format)?;
+    report_failures(&stats.failures);
+    if strict && !stats.failures.is_empty() {
+        return Err(Error::Parse {
+            failed: stats.failures.len(),
+        });
+    }
     Ok(())
 }
 
+fn serve(mut args: env::Args) -> Result<()> {
+    let mut index_file = None;
+    let mut address = None;
+    let mut watch_dir = None;
+    let mut options = IndexOptions::default();
+    let mut index_options = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" => watch_dir = Some(option_value(&arg, &mut args)?),
+            _ if index_option(&arg, &mut args, &mut options)? => index_options = true,
+            _ if arg.starts_with('-') => return Err(unknown_option(&arg)),
+            _ if index_file.is_none() => index_file = Some(arg),
+            _ if address.is_none() => address = Some(arg),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let index_file =
+        index_file.ok_or_else(|| Error::Usage("no index file provided for serving".to_string()))?;
+    let index_file = Path::new(&index_file);
+    let address = address.unwrap_or("127.0.0.1:8000".to_string());
+
+    let Some(watch_dir) = watch_dir else {
+        if index_options {
+            return Err(Error::Usage(
+                "indexing options only apply with --watch".to_string(),
+            ));
+        }
+        let index = RwLock::new(open_index(index_file)?);
+        return start(&address, &index, &Scorers::default());
+    };
+
+    let watch_dir = PathBuf::from(watch_dir);
+    options.overrides(&watch_dir).map_err(Error::Usage)?;
+    // Watched indexes must be fully loaded to be updated in memory
+    let mut model = load_index(index_file)?;
+    let watcher = watch_folder(&watch_dir)?;
+    // Files may have changed since the index was saved
+    report_update(&update_folder_in_model(&watch_dir, &mut model, &options));
+    let model = Arc::new(RwLock::new(model));
+    let watched_model = Arc::clone(&model);
+    thread::spawn(move || watcher.run(&watched_model, &options));
+    start(&address, &model, &Scorers::default())
+}
+
+fn entry(mut args: env::Args) -> Result<()> {
+    let subcommand = args
+        .next()
+        .ok_or_else(|| Error::Usage("no subcommand is provided".to_string()))?;
+
+    match subcommand.as_str() {
+        "index" => index(args),
+        "search" => search(args),
+        "terms" => terms(args),
+        "serve" => serve(args),
+        _ => Err(Error::Usage(format!("unknown subcommand {subcommand}"))),
+    }
+}
+
 fn main() -> ExitCode {
-    match entry() {
+    let mut args = env::args();
+    let program = args.next().expect("path to program is provided");
+    match entry(args) {
         Ok(()) => ExitCode::SUCCESS,
-        Err(()) => ExitCode::FAILURE,
+        Err(err) => {
+            if let Error::Usage(_) = err {
+                usage(&program);
+            }
+            eprintln!("Error: {err}");
+            ExitCode::from(err.exit_code())
+        }
     }
 }
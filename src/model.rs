@@ -1,134 +1,543 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{self, File};
-use std::io::{self, BufReader, BufWriter};
+use std::io::{self, BufWriter, Read};
+use std::ops::Bound;
 use std::path::{Path, PathBuf};
-use xml::reader::EventReader;
-use xml::reader::XmlEvent;
+use std::time::UNIX_EPOCH;
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::*;
 use crate::lexer::*;
+use crate::query::*;
+use crate::scorer::*;
+use crate::storage::*;
 
 // TYPES DEFINITIONS
-pub type DocFreq = HashMap<String, usize>;
-pub type TermFreq = HashMap<String, usize>;
-pub type TermFreqPerDoc = HashMap<PathBuf, (usize, TermFreq)>;
+/// Positions of every term of a document, counted in tokens
+pub type TermPositions = HashMap<String, Vec<usize>>;
+pub type DocId = usize;
+/// Words every term was stemmed from, with how many times each occurred
+pub type SurfaceForms = BTreeMap<String, BTreeMap<String, usize>>;
+
+/// Modification time, size and content hash of an indexed file, used to
+/// skip unchanged files when updating an index
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FileStamp {
+    /// Nanoseconds since the Unix epoch
+    pub mtime: u64,
+    pub size: u64,
+    pub hash: u64,
+}
+
+impl FileStamp {
+    pub fn new(metadata: &fs::Metadata, content: &[u8]) -> Self {
+        let mut stamp = Self::from_metadata(metadata);
+        stamp.hash = content_hash(content);
+        stamp
+    }
+
+    /// Stamp without the content hash
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |mtime| mtime.as_nanos() as u64);
+        Self {
+            mtime,
+            size: metadata.len(),
+            hash: 0,
+        }
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds unlike `DefaultHasher`
+pub fn content_hash(content: &[u8]) -> u64 {
+    content.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Document {
+    /// File the document was read from
+    pub path: PathBuf,
+    /// Suffix locating the document inside a file holding several, like
+    /// `#page=3` for a page of a PDF
+    #[serde(default)]
+    pub part: Option<String>,
+    #[serde(default)]
+    pub stamp: FileStamp,
+    /// Number of tokens in the document
+    pub length: usize,
+    /// Euclidean norm of the document's log-weighted term vector
+    pub norm: f32,
+}
+
+pub fn full_path(path: &Path, part: Option<&str>) -> PathBuf {
+    match part {
+        Some(part) => {
+            let mut path = path.as_os_str().to_owned();
+            path.push(part);
+            PathBuf::from(path)
+        }
+        None => path.to_path_buf(),
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Posting {
+    pub doc: DocId,
+    pub tf: usize,
+    /// Where the term occurs in the document, in increasing order. Empty
+    /// for indexes saved before positions were recorded.
+    #[serde(default)]
+    pub positions: Vec<usize>,
+    /// Words the term was stemmed from in the document with how often each
+    /// occurred, sorted by word. Empty for indexes saved before surface
+    /// forms were recorded, and for postings of a memory mapped index,
+    /// which keeps the counts of the whole index next to the postings.
+    #[serde(default)]
+    pub forms: Vec<(String, usize)>,
+}
+
+/// Postings of every term, each list sorted by document id. Terms are kept
+/// sorted so the ones sharing a prefix can be enumerated.
+pub type Postings = BTreeMap<String, Vec<Posting>>;
 
 #[derive(Default, Deserialize, Serialize)]
 pub struct Model {
-    pub df: DocFreq,
-    pub tfpd: TermFreqPerDoc,
+    pub docs: Vec<Document>,
+    pub postings: Postings,
 }
 
-pub fn compute_tf(t: &str, d: &(usize, TermFreq)) -> f32 {
-    let sum: usize = d.0;
-    *d.1.get(t).unwrap_or(&0) as f32 / sum as f32
+impl Model {
+    pub fn add_document(
+        &mut self,
+        path: PathBuf,
+        part: Option<String>,
+        stamp: FileStamp,
+        terms: TermPositions,
+        mut forms: SurfaceForms,
+    ) {
+        let doc = self.docs.len();
+        self.docs.push(Document {
+            path,
+            part,
+            stamp,
+            length: terms.values().map(Vec::len).sum(),
+            norm: terms
+                .values()
+                .map(|positions| log_tf(positions.len()).powi(2))
+                .sum::<f32>()
+                .sqrt(),
+        });
+        for (term, positions) in terms {
+            let term_forms = forms.remove(&term).into_iter().flatten().collect();
+            self.postings.entry(term).or_default().push(Posting {
+                doc,
+                tf: positions.len(),
+                positions,
+                forms: term_forms,
+            });
+        }
+    }
+
+    /// Remove the documents and their postings, renumbering the documents
+    /// that remain
+    pub fn remove_documents(&mut self, removed: &HashSet<DocId>) {
+        if removed.is_empty() {
+            return;
+        }
+        let mut remap = Vec::with_capacity(self.docs.len());
+        let mut next = 0;
+        for doc in 0..self.docs.len() {
+            remap.push(next);
+            if !removed.contains(&doc) {
+                next += 1;
+            }
+        }
+        let mut doc = 0;
+        self.docs.retain(|_| {
+            doc += 1;
+            !removed.contains(&(doc - 1))
+        });
+        self.postings.retain(|_, postings| {
+            postings.retain(|posting| !removed.contains(&posting.doc));
+            for posting in postings.iter_mut() {
+                posting.doc = remap[posting.doc];
+            }
+            !postings.is_empty()
+        });
+    }
 }
 
-pub fn compute_idf(t: &str, n: usize, df: &DocFreq) -> f32 {
-    let n = n as f32;
-    let m: f32 = df.get(t).cloned().unwrap_or(0) as f32;
-    f32::log10(n / (1.0 + m))
+/// Surface forms of a term summed over its postings
+pub fn total_forms<'a>(postings: impl Iterator<Item = &'a Posting>) -> BTreeMap<String, usize> {
+    let mut forms = BTreeMap::new();
+    for (form, count) in postings.flat_map(|posting| &posting.forms) {
+        *forms.entry(form.clone()).or_default() += count;
+    }
+    forms
 }
 
-pub fn read_xml_file(file_path: &Path) -> io::Result<String> {
-    let file = File::open(file_path)?;
-    let file = BufReader::new(file);
-    let reader = EventReader::new(file);
-    let mut contents = String::new();
-    for result in reader.into_iter() {
-        if let XmlEvent::Characters(text) = result.expect("TODO") {
-            contents.push_str(&text);
-            contents.push_str(" "); // Pad last word
-        }
+/// Sort surface forms by decreasing count, ties in alphabetical order
+pub fn most_common_first(forms: impl Iterator<Item = (String, usize)>) -> Vec<(String, usize)> {
+    let mut forms = forms.collect::<Vec<_>>();
+    forms.sort_by(|(form1, count1), (form2, count2)| count2.cmp(count1).then(form1.cmp(form2)));
+    forms
+}
+
+/// Read access to an index, either fully loaded or memory mapped
+pub trait IndexReader {
+    fn corpus_stats(&self) -> CorpusStats;
+    fn doc_stats(&self, doc: DocId) -> Result<DocStats>;
+    /// File the document comes from and the part of the file it is
+    fn doc_location(&self, doc: DocId) -> Result<(PathBuf, Option<String>)>;
+    /// Documents read from the file at `path`, like the pages of a PDF or
+    /// the entries of an archive, in no particular order
+    fn file_docs(&self, path: &Path) -> Result<Vec<DocId>>;
+
+    /// Path of the document, including the part of the file it comes from
+    fn doc_path(&self, doc: DocId) -> Result<PathBuf> {
+        let (path, part) = self.doc_location(doc)?;
+        Ok(full_path(&path, part.as_deref()))
+    }
+
+    /// Postings of `term`, empty if the term is not indexed
+    fn postings(&self, term: &str) -> Result<Cow<'_, [Posting]>>;
+
+    /// Number of documents containing `term`
+    fn df(&self, term: &str) -> Result<usize> {
+        Ok(self.postings(term)?.len())
+    }
+
+    /// Words `term` was stemmed from with how often each occurred, most
+    /// common first
+    fn surface_forms(&self, term: &str) -> Result<Vec<(String, usize)>>;
+
+    /// Most common word `term` was stemmed from, the term itself for
+    /// indexes saved before surface forms were recorded
+    fn surface_form(&self, term: &str) -> Result<String> {
+        let mut forms = self.surface_forms(term)?.into_iter();
+        Ok(forms
+            .next()
+            .map_or_else(|| term.to_string(), |(form, _)| form))
+    }
+
+    /// Call `visit` with every indexed term from `from` on, in order, until
+    /// it returns false
+    fn visit_terms_from(&self, from: &str, visit: &mut dyn FnMut(&str) -> bool) -> Result<()>;
+
+    /// Call `visit` with every indexed term starting with `prefix`, in
+    /// order, until it returns false
+    fn visit_terms(&self, prefix: &str, visit: &mut dyn FnMut(&str) -> bool) -> Result<()> {
+        self.visit_terms_from(prefix, &mut |term| term.starts_with(prefix) && visit(term))
+    }
+}
+
+impl<T: IndexReader + ?Sized> IndexReader for Box<T> {
+    fn corpus_stats(&self) -> CorpusStats {
+        (**self).corpus_stats()
+    }
+
+    fn doc_stats(&self, doc: DocId) -> Result<DocStats> {
+        (**self).doc_stats(doc)
+    }
+
+    fn doc_location(&self, doc: DocId) -> Result<(PathBuf, Option<String>)> {
+        (**self).doc_location(doc)
+    }
+
+    fn file_docs(&self, path: &Path) -> Result<Vec<DocId>> {
+        (**self).file_docs(path)
+    }
+
+    fn postings(&self, term: &str) -> Result<Cow<'_, [Posting]>> {
+        (**self).postings(term)
+    }
+
+    fn df(&self, term: &str) -> Result<usize> {
+        (**self).df(term)
+    }
+
+    fn surface_forms(&self, term: &str) -> Result<Vec<(String, usize)>> {
+        (**self).surface_forms(term)
+    }
+
+    fn visit_terms_from(&self, from: &str, visit: &mut dyn FnMut(&str) -> bool) -> Result<()> {
+        (**self).visit_terms_from(from, visit)
     }
-    Ok(contents)
 }
 
-pub fn add_folder_to_model(dir_path: &Path, model: &mut Model) {
-    let dir = fs::read_dir(dir_path).unwrap();
-    let ext_xhtml = std::ffi::OsStr::new("xhtml");
-    for file in dir {
-        let file_path = file.unwrap().path();
-        if file_path.is_dir() {
-            add_folder_to_model(&file_path, model);
+impl IndexReader for Model {
+    fn corpus_stats(&self) -> CorpusStats {
+        let total: usize = self.docs.iter().map(|doc| doc.length).sum();
+        CorpusStats {
+            doc_count: self.docs.len(),
+            avg_doc_length: total as f32 / self.docs.len().max(1) as f32,
         }
+    }
 
-        let ext = file_path
-            .extension()
-            .unwrap_or_else(|| std::ffi::OsStr::new(""));
+    fn doc_stats(&self, doc: DocId) -> Result<DocStats> {
+        let doc = &self.docs[doc];
+        Ok(DocStats {
+            length: doc.length,
+            norm: doc.norm,
+        })
+    }
+
+    fn doc_location(&self, doc: DocId) -> Result<(PathBuf, Option<String>)> {
+        let doc = &self.docs[doc];
+        Ok((doc.path.clone(), doc.part.clone()))
+    }
 
-        if ext_xhtml == ext {
-            let content = read_xml_file(&file_path)
-                .unwrap()
-                .chars()
-                .collect::<Vec<_>>();
+    fn file_docs(&self, path: &Path) -> Result<Vec<DocId>> {
+        Ok((0..self.docs.len())
+            .filter(|doc| self.docs[*doc].path == path)
+            .collect())
+    }
 
-            println!("Indexing... {file_path:?}");
+    fn postings(&self, term: &str) -> Result<Cow<'_, [Posting]>> {
+        Ok(self
+            .postings
+            .get(term)
+            .map_or(Cow::Borrowed(&[]), |postings| Cow::Borrowed(postings)))
+    }
 
-            let mut tf = TermFreq::new();
+    fn surface_forms(&self, term: &str) -> Result<Vec<(String, usize)>> {
+        let postings = self.postings.get(term).into_iter().flatten();
+        Ok(most_common_first(total_forms(postings).into_iter()))
+    }
 
-            for term in Lexer::new(&content) {
-                if let Some(count) = tf.get_mut(&term) {
-                    *count += 1;
-                } else {
-                    tf.insert(term, 1);
-                }
+    fn visit_terms_from(&self, from: &str, visit: &mut dyn FnMut(&str) -> bool) -> Result<()> {
+        let range = (Bound::Included(from), Bound::Unbounded);
+        for term in self.postings.range::<str, _>(range).map(|(term, _)| term) {
+            if !visit(term) {
+                break;
             }
+        }
+        Ok(())
+    }
+}
 
-            let mut term_sum: usize = 0;
-            for t in tf.keys() {
-                if let Some(freq) = model.df.get_mut(t) {
-                    *freq += 1;
-                } else {
-                    model.df.insert(t.to_string(), 1);
-                };
-                term_sum += 1;
-            }
+#[derive(Clone, Copy, Default)]
+pub enum IndexFormat {
+    #[default]
+    Binary,
+    Json,
+}
 
-            let mut stats = tf.iter().collect::<Vec<_>>();
-            stats.sort_by_key(|(_, f)| *f);
-            stats.reverse();
+impl IndexFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bin" | "binary" => Some(Self::Binary),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
 
-            model.tfpd.insert(file_path, (term_sum, tf));
+    pub fn default_file_name(self) -> &'static str {
+        match self {
+            Self::Binary => "index.bin",
+            Self::Json => "index.json",
+        }
+    }
+
+    /// Format of an existing index file, `None` if it can't be read
+    pub fn of_file(index_path: &Path) -> Option<Self> {
+        let mut magic = [0; MAGIC.len()];
+        let mut file = File::open(index_path).ok()?;
+        match file.read_exact(&mut magic) {
+            Ok(()) if is_binary_index(&magic) => Some(Self::Binary),
+            _ => Some(Self::Json),
         }
     }
 }
 
-pub fn save_model_as_json(index_file: &str, model: &Model) -> std::io::Result<()> {
-    let index_path = index_file;
-    println!("Saving {index_path}...");
+pub fn save_model_as_json(index_path: &Path, model: &Model) -> io::Result<()> {
     let index_file = File::create(index_path)?;
     let index_file = BufWriter::new(index_file);
-    serde_json::to_writer(index_file, model).map_err(|err| {
-        eprintln!("Error: couldn't save model as JSON; {err}");
-    }).unwrap();
-    Ok(())
-}
-
-pub fn load_index(index_path: &Path) -> Result<Model, ()> {
-    let index_file = File::open(index_path).unwrap();
-    let mut index_reader = io::BufReader::new(index_file);
-    let model: Model =
-        serde_json::from_reader(&mut index_reader).expect("serde works fine");
-    Ok(model)
-}
-
-pub fn search_query<'a>(
-    query: &'a Vec<char>,
-    model: &'a Model,
-) -> Vec<(&'a Path, f32)> {
-    let mut tf_idf = 0.0;
-    let mut rank = Vec::<(&Path, f32)>::new();
-    for (file, d) in model.tfpd.iter() {
-        for token in Lexer::new(&query) {
-            tf_idf += compute_tf(&token, &d) * compute_idf(&token, model.tfpd.len(), &model.df);
+    serde_json::to_writer(index_file, model).map_err(io::Error::from)
+}
+
+pub fn save_index(index_path: &Path, model: &Model, format: IndexFormat) -> Result<()> {
+    println!("Saving {}...", index_path.display());
+    match format {
+        IndexFormat::Binary => replace_index(index_path, |path| save_model_as_binary(path, model)),
+        IndexFormat::Json => replace_index(index_path, |path| save_model_as_json(path, model)),
+    }
+    .map_err(|err| {
+        Error::io(
+            format!("could not save index into {}", index_path.display()),
+            err,
+        )
+    })
+}
+
+pub fn load_index(index_path: &Path) -> Result<Model> {
+    let bytes = fs::read(index_path).map_err(|err| Error::index_io(index_path, err))?;
+    if is_binary_index(&bytes) {
+        return decode_model(&bytes).map_err(|err| Error::index_format(index_path, err));
+    }
+    serde_json::from_slice(&bytes).map_err(|err| Error::index_format(index_path, err))
+}
+
+/// Open an index for querying. Binary indexes are memory mapped, JSON
+/// indexes are loaded into memory.
+pub fn open_index(index_path: &Path) -> Result<Box<dyn IndexReader>> {
+    if !matches!(IndexFormat::of_file(index_path), Some(IndexFormat::Binary)) {
+        return Ok(Box::new(load_index(index_path)?));
+    }
+    Ok(Box::new(MappedIndex::open(index_path)?))
+}
+
+/// How queries are evaluated
+pub struct SearchOptions {
+    /// Scores of documents where the query terms occur close together are
+    /// raised by up to `proximity` times, see [`proximity`]
+    pub proximity: f32,
+    /// Look up terms missing from the index as if they were followed by `~`
+    pub fuzzy: bool,
+    /// Keep where every document matched, see [`SearchResults::matches`]
+    pub matches: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            proximity: PROXIMITY_WEIGHT,
+            fuzzy: false,
+            matches: false,
         }
-        if tf_idf > 0.0 {
-            rank.push((file, tf_idf));
+    }
+}
+
+pub struct SearchResults {
+    /// Documents matching the query, best first
+    pub rank: Vec<(DocId, f32)>,
+    /// Width and positions of every term and phrase each document matched,
+    /// only kept if asked for in [`SearchOptions::matches`]
+    pub matches: Matches,
+}
+
+/// Documents matching the query, see [`crate::query`]
+pub fn search_query(
+    query: &[char],
+    index: &dyn IndexReader,
+    scorer: &dyn Scorer,
+    options: &SearchOptions,
+) -> Result<SearchResults> {
+    let query = parse_query(query).map_err(Error::Query)?;
+    let mut search = Search::new(index, scorer, options);
+    let mut scores = search.evaluate(&query)?;
+    for (doc, doc_matches) in &search.matches {
+        let matched = doc_matches
+            .iter()
+            .map(|(width, positions)| (*width, positions.as_slice()))
+            .collect::<Vec<_>>();
+        if let Some(score) = scores.get_mut(doc) {
+            *score *= 1.0 + options.proximity * proximity(&matched);
         }
-        tf_idf = 0.0;
     }
-    rank.sort_by(|(_, rank1), (_, rank2)| rank2.partial_cmp(rank1).unwrap());
-    rank
+    if !options.matches {
+        search.matches.clear();
+    }
+    // Every document matching the query is a result, the score only ranks it
+    let mut rank = scores.into_iter().collect::<Vec<_>>();
+    rank.sort_by(|(doc1, rank1), (doc2, rank2)| rank2.total_cmp(rank1).then(doc1.cmp(doc2)));
+    Ok(SearchResults {
+        rank,
+        matches: search.matches,
+    })
+}
+
+/// What the index knows about a term
+#[derive(Serialize)]
+pub struct TermInfo {
+    pub term: String,
+    pub df: usize,
+    /// Words the term was stemmed from, most common first
+    pub forms: Vec<(String, usize)>,
+}
+
+/// Terms of `text` as the lexer splits and stems it, each only once
+pub fn inspect_terms(index: &dyn IndexReader, text: &[char]) -> Result<Vec<TermInfo>> {
+    let mut terms = Vec::<TermInfo>::new();
+    for term in Lexer::new(text) {
+        if terms.iter().any(|info| info.term == term) {
+            continue;
+        }
+        terms.push(TermInfo {
+            df: index.df(&term)?,
+            forms: index.surface_forms(&term)?,
+            term,
+        });
+    }
+    Ok(terms)
+}
+
+/// Paths of the first `limit` ranked documents, all of them if `limit` is 0
+pub fn resolve_paths(
+    index: &dyn IndexReader,
+    rank: &[(DocId, f32)],
+    limit: usize,
+) -> Result<Vec<(PathBuf, f32)>> {
+    let limit = if limit == 0 { rank.len() } else { limit };
+    rank.iter()
+        .take(limit)
+        .map(|(doc, score)| Ok((index.doc_path(*doc)?, *score)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(model: &mut Model, path: &str, words: &[(&str, &str)]) {
+        let mut terms = TermPositions::new();
+        let mut forms = SurfaceForms::new();
+        for (position, (term, form)) in words.iter().enumerate() {
+            terms.entry(term.to_string()).or_default().push(position);
+            *forms
+                .entry(term.to_string())
+                .or_default()
+                .entry(form.to_string())
+                .or_default() += 1;
+        }
+        let stamp = FileStamp::default();
+        model.add_document(PathBuf::from(path), None, stamp, terms, forms);
+    }
+
+    #[test]
+    fn removed_documents_no_longer_count_towards_surface_forms() {
+        let mut model = Model::default();
+        add(
+            &mut model,
+            "a.txt",
+            &[("comput", "computers"), ("comput", "computing")],
+        );
+        add(
+            &mut model,
+            "b.txt",
+            &[("comput", "computers"), ("run", "runs")],
+        );
+        add(&mut model, "c.txt", &[("comput", "computer")]);
+        let forms = |model: &Model, term| model.surface_forms(term).unwrap();
+        assert_eq!(
+            forms(&model, "comput"),
+            [
+                ("computers".to_string(), 2),
+                ("computer".to_string(), 1),
+                ("computing".to_string(), 1)
+            ]
+        );
+
+        model.remove_documents(&HashSet::from([0, 1]));
+        assert_eq!(forms(&model, "comput"), [("computer".to_string(), 1)]);
+        assert!(forms(&model, "run").is_empty());
+        assert_eq!(model.surface_form("run").unwrap(), "run");
+    }
 }
@@ -0,0 +1,475 @@
+use std::collections::HashSet;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use xml::reader::EventReader;
+use xml::reader::XmlEvent;
+
+use crate::archive::*;
+
+/// Extracts the indexable text of a document
+pub trait DocumentParser: Send + Sync {
+    /// File extensions handled by the parser, lowercase and without the dot
+    fn extensions(&self) -> &'static [&'static str];
+    /// Content type to serve the original document with
+    fn mime_type(&self) -> &'static str;
+    fn parse(&self, content: &[u8]) -> io::Result<String>;
+
+    /// Text of every separately indexed part of the file, with the suffix
+    /// that locates it (see [`crate::model::Document::part`])
+    fn parse_parts(&self, content: &[u8]) -> io::Result<Vec<(Option<String>, String)>> {
+        Ok(vec![(None, self.parse(content)?)])
+    }
+
+    /// Text of the given parts of the file, in the same order, `None` for
+    /// parts the file doesn't have. Only these parts need to be extracted.
+    fn parse_selected_parts(
+        &self,
+        content: &[u8],
+        parts: &[Option<&str>],
+    ) -> io::Result<Vec<Option<String>>> {
+        let all = self.parse_parts(content)?;
+        Ok(parts
+            .iter()
+            .map(|part| {
+                all.iter()
+                    .find(|(other, _)| other.as_deref() == *part)
+                    .map(|(_, text)| text.clone())
+            })
+            .collect())
+    }
+}
+
+pub fn read_xml(content: impl Read) -> io::Result<String> {
+    let reader = EventReader::new(BufReader::new(content));
+    let mut contents = String::new();
+    for result in reader.into_iter() {
+        let event = result.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if let XmlEvent::Characters(text) = event {
+            contents.push_str(&text);
+            contents.push(' '); // Pad last word
+        }
+    }
+    Ok(contents)
+}
+
+pub struct XhtmlParser;
+
+impl DocumentParser for XhtmlParser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["xhtml"]
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "application/xhtml+xml"
+    }
+
+    fn parse(&self, content: &[u8]) -> io::Result<String> {
+        read_xml(content)
+    }
+}
+
+pub struct TextParser;
+
+impl DocumentParser for TextParser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["txt", "text"]
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "text/plain; charset=utf-8"
+    }
+
+    fn parse(&self, content: &[u8]) -> io::Result<String> {
+        Ok(String::from_utf8_lossy(content).into_owned())
+    }
+}
+
+/// Tag soup parser: keeps the text outside of tags, comments, scripts and
+/// styles, and doesn't care whether tags are balanced
+pub struct HtmlParser;
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+/// Copy `text` into `out` replacing character references
+fn push_decoded(out: &mut String, text: &str) {
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| Some((decode_entity(&rest[1..end + 1])?, end + 2)));
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+pub fn strip_html(html: &str) -> String {
+    let mut contents = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(open) = rest.find('<') {
+        push_decoded(&mut contents, &rest[..open]);
+        contents.push(' ');
+        rest = &rest[open..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some(close) = rest.find('>') else {
+            // Unterminated tag, the rest is most likely text
+            push_decoded(&mut contents, &rest[1..]);
+            rest = "";
+            break;
+        };
+        let name = rest[1..close]
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        rest = &rest[close + 1..];
+        if name == "script" || name == "style" {
+            let end_tag = format!("</{name}");
+            let lowercase = rest.to_ascii_lowercase();
+            rest = match lowercase.find(&end_tag) {
+                Some(end) => &rest[end..],
+                None => "",
+            };
+        }
+    }
+    push_decoded(&mut contents, rest);
+    contents
+}
+
+impl DocumentParser for HtmlParser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["html", "htm"]
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "text/html; charset=utf-8"
+    }
+
+    fn parse(&self, content: &[u8]) -> io::Result<String> {
+        Ok(strip_html(&String::from_utf8_lossy(content)))
+    }
+}
+
+/// Keeps the text of a Markdown document, dropping the markup around it
+pub struct MarkdownParser;
+
+/// Replace `[text](target)` and `![text](target)` with `text`
+fn strip_links(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find("](").map(|close| open + close) else {
+            break;
+        };
+        let Some(end) = rest[close..].find(')').map(|end| close + end) else {
+            break;
+        };
+        stripped.push_str(rest[..open].trim_end_matches('!'));
+        stripped.push_str(&rest[open + 1..close]);
+        rest = &rest[end + 1..];
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+pub fn strip_markdown(markdown: &str) -> String {
+    let mut contents = String::with_capacity(markdown.len());
+    for line in markdown.lines() {
+        let line = line.trim_start();
+        // Code fences and reference definitions carry no text
+        if line.starts_with("```") || line.starts_with("~~~") {
+            continue;
+        }
+        if line.starts_with('[') && line.contains("]:") {
+            continue;
+        }
+        let line = line.trim_start_matches(['#', '>', ' ']);
+        let line = match line.split_once(' ') {
+            Some((marker, item)) if marker == "-" || marker == "*" || marker == "+" => item,
+            Some((marker, item))
+                if marker.len() > 1
+                    && marker.ends_with(['.', ')'])
+                    && marker[..marker.len() - 1]
+                        .chars()
+                        .all(|c| c.is_ascii_digit()) =>
+            {
+                item
+            }
+            _ => line,
+        };
+        let line = strip_links(line).replace(['*', '_', '`', '~', '|'], " ");
+        contents.push_str(&strip_html(&line));
+        contents.push('\n');
+    }
+    contents
+}
+
+impl DocumentParser for MarkdownParser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["md", "markdown"]
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "text/markdown; charset=utf-8"
+    }
+
+    fn parse(&self, content: &[u8]) -> io::Result<String> {
+        Ok(strip_markdown(&String::from_utf8_lossy(content)))
+    }
+}
+
+/// Indexes every page of a PDF as its own document, located by the
+/// `#page=N` fragment PDF viewers understand
+#[cfg(feature = "pdf")]
+pub struct PdfParser;
+
+#[cfg(feature = "pdf")]
+impl PdfParser {
+    fn load(content: &[u8]) -> io::Result<lopdf::Document> {
+        lopdf::Document::load_mem(content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+    }
+
+    fn page_text(pdf: &lopdf::Document, page: u32) -> io::Result<String> {
+        pdf.extract_text(&[page])
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+    }
+}
+
+#[cfg(feature = "pdf")]
+impl DocumentParser for PdfParser {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["pdf"]
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "application/pdf"
+    }
+
+    fn parse(&self, content: &[u8]) -> io::Result<String> {
+        let pdf = Self::load(content)?;
+        let mut contents = String::new();
+        for page in pdf.get_pages().into_keys() {
+            contents.push_str(&Self::page_text(&pdf, page)?);
+            contents.push('\n');
+        }
+        Ok(contents)
+    }
+
+    fn parse_parts(&self, content: &[u8]) -> io::Result<Vec<(Option<String>, String)>> {
+        let pdf = Self::load(content)?;
+        pdf.get_pages()
+            .into_keys()
+            .map(|page| Ok((Some(format!("#page={page}")), Self::page_text(&pdf, page)?)))
+            .collect()
+    }
+
+    fn parse_selected_parts(
+        &self,
+        content: &[u8],
+        parts: &[Option<&str>],
+    ) -> io::Result<Vec<Option<String>>> {
+        let pdf = Self::load(content)?;
+        let pages = pdf.get_pages();
+        parts
+            .iter()
+            .map(|part| {
+                let page = part
+                    .and_then(|part| part.strip_prefix("#page="))
+                    .and_then(|page| page.parse().ok())
+                    .filter(|page| pages.contains_key(page));
+                page.map(|page| Self::page_text(&pdf, page)).transpose()
+            })
+            .collect()
+    }
+}
+
+/// The parsers available for indexing and the extensions they're used for
+pub struct Parsers {
+    parsers: Vec<Box<dyn DocumentParser>>,
+    /// Indexed extensions, all the supported ones if `None`
+    enabled: Option<HashSet<String>>,
+}
+
+impl Default for Parsers {
+    fn default() -> Self {
+        Self {
+            parsers: vec![
+                Box::new(XhtmlParser),
+                Box::new(HtmlParser),
+                Box::new(MarkdownParser),
+                Box::new(TextParser),
+                #[cfg(feature = "pdf")]
+                Box::new(PdfParser),
+            ],
+            enabled: None,
+        }
+    }
+}
+
+impl Parsers {
+    /// Only index files with one of `extensions`, which must all be supported
+    pub fn enable_only(&mut self, extensions: &[&str]) -> Result<(), String> {
+        let mut enabled = HashSet::new();
+        for ext in extensions {
+            let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+            let archive = ARCHIVE_EXTENSIONS.contains(&ext.as_str());
+            if !archive && !self.supported().any(|supported| supported == ext) {
+                return Err(format!("no parser for the extension {ext}"));
+            }
+            enabled.insert(ext);
+        }
+        self.enabled = Some(enabled);
+        Ok(())
+    }
+
+    pub fn supported(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.parsers
+            .iter()
+            .flat_map(|parser| parser.extensions().iter().copied())
+    }
+
+    /// Content type of a supported file, whether it's indexed or not
+    pub fn mime_type(&self, path: &Path) -> Option<&'static str> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        self.parsers
+            .iter()
+            .find(|parser| parser.extensions().contains(&ext.as_str()))
+            .map(|parser| parser.mime_type())
+    }
+
+    /// Whether the archive at `path` is worth opening for the enabled
+    /// extensions, see [`ArchiveKind::may_contain`]
+    pub fn opens_archive(&self, path: &Path) -> bool {
+        ArchiveKind::of(path).is_some()
+            && self
+                .enabled
+                .as_ref()
+                .is_none_or(|enabled| ArchiveKind::may_contain(path, enabled))
+    }
+
+    pub fn for_path(&self, path: &Path) -> Option<&dyn DocumentParser> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        if let Some(enabled) = &self.enabled {
+            if !enabled.contains(&ext) {
+                return None;
+            }
+        }
+        self.parsers
+            .iter()
+            .find(|parser| parser.extensions().contains(&ext.as_str()))
+            .map(|parser| parser.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> String {
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn html_text_outside_tags() {
+        let html = "<p class=\"x\">Hello <b>big</b><br/>world</p><!-- note -->!";
+        assert_eq!(words(&strip_html(html)), "Hello big world !");
+        // Tags split words even without whitespace around them
+        assert_eq!(words(&strip_html("one<br>two")), "one two");
+    }
+
+    #[test]
+    fn html_unterminated_tags_and_comments() {
+        assert_eq!(words(&strip_html("a < b and c")), "a b and c");
+        assert_eq!(words(&strip_html("text <!-- never closed")), "text");
+    }
+
+    #[test]
+    fn html_skips_scripts_and_styles_in_any_case() {
+        let html = "a<SCRIPT type=x>var b = '<p>';</Script>c<style>p {}</STYLE>d";
+        assert_eq!(words(&strip_html(html)), "a c d");
+        assert_eq!(words(&strip_html("a<script>never closed")), "a");
+    }
+
+    #[test]
+    fn character_references() {
+        let mut out = String::new();
+        push_decoded(
+            &mut out,
+            "&lt;a&gt; &amp;&#65;&#x42;&#X43;&nbsp;&quot;&apos;",
+        );
+        assert_eq!(out, "<a> &ABC \"'");
+
+        // Unknown, invalid and unterminated references are kept as they are
+        let mut out = String::new();
+        push_decoded(&mut out, "&bogus; &#xZZ; &#1114112; & AT&T");
+        assert_eq!(out, "&bogus; &#xZZ; &#1114112; & AT&T");
+
+        // A `;` further than any entity name is not the end of one
+        let mut out = String::new();
+        push_decoded(&mut out, "&averyverylongname; &amp;");
+        assert_eq!(out, "&averyverylongname; &");
+    }
+
+    #[test]
+    fn markdown_links() {
+        assert_eq!(
+            strip_links("see [the docs](http://x) and ![logo](logo.png)."),
+            "see the docs and logo."
+        );
+        assert_eq!(
+            strip_links("[not a link] (x) [open"),
+            "[not a link] (x) [open"
+        );
+    }
+
+    #[test]
+    fn markdown_markup() {
+        let markdown = "\
+# Title
+> quoted *text*
+- item
+12. numbered
+3) other
+2024 was a year
+```rust
+fn code() {}
+```
+[ref]: http://example.com
+| a | b |";
+        let expected = "Title quoted text item numbered other 2024 was a year \
+                        fn code() {} a b";
+        assert_eq!(words(&strip_markdown(markdown)), expected);
+    }
+}
@@ -0,0 +1,826 @@
+//! Query language. Bare words match a document wherever they occur in it
+//! and the more of them a document contains the better it ranks. On top of
+//! that:
+//!
+//! ```text
+//! "memory safety"     words in double quotes must be adjacent and in order
+//! +word, -word        the document must, or must not, contain the word
+//! a AND b, a OR b     both, or either, must match
+//! NOT a               same as -a
+//! (a OR b) c          parentheses group queries
+//! alloc*, te?t        `*` stands for any characters, `?` for a single one
+//! memroy~2, safty~    terms within 2 edits, or as many as fit the length
+//! ```
+//!
+//! Patterns and fuzzy terms are matched against the indexed terms, which
+//! are stemmed.
+//!
+//! `AND` binds tighter than `OR`, which binds tighter than juxtaposition.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::error::*;
+use crate::fuzzy::*;
+use crate::lexer::*;
+use crate::model::*;
+use crate::scorer::*;
+
+/// Most terms a pattern or fuzzy term is expanded to, the rest are ignored
+pub const MAX_EXPANSIONS: usize = 128;
+
+#[derive(Debug, PartialEq)]
+pub enum Node {
+    Term(String),
+    /// Terms that must be adjacent and in this order
+    Phrase(Vec<String>),
+    /// Any term matching the wildcard pattern
+    Pattern(String),
+    /// Any term within `max_edits` of the term
+    Fuzzy {
+        term: String,
+        max_edits: usize,
+    },
+    /// Documents must match every `must` node and none of the `must_not`
+    /// nodes. Without `must` nodes they have to match a `should` node.
+    Bool {
+        must: Vec<Node>,
+        should: Vec<Node>,
+        must_not: Vec<Node>,
+    },
+}
+
+/// Why a query couldn't be parsed
+#[derive(Debug, Serialize)]
+pub struct QueryError {
+    pub message: String,
+    /// Offset in characters of the query where the problem is
+    pub position: usize,
+}
+
+impl QueryError {
+    fn new(message: impl Into<String>, position: usize) -> Self {
+        Self {
+            message: message.into(),
+            position,
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum QueryToken {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Plus,
+    Minus,
+    Phrase(Vec<String>),
+    Word(Vec<String>),
+    Pattern(String),
+    /// Terms of the word and the edits asked for after its `~`, if any
+    Fuzzy(Vec<String>, Option<usize>),
+}
+
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')' || c == '"'
+}
+
+fn is_wildcard(c: char) -> bool {
+    c == '*' || c == '?'
+}
+
+/// Split `word~2` or `word~` into the word and the edits
+fn split_fuzzy(word: &[char]) -> Option<(&[char], Option<usize>)> {
+    let tilde = word.iter().rposition(|c| *c == '~')?;
+    let (base, edits) = (&word[..tilde], &word[tilde + 1..]);
+    if base.is_empty() || !edits.iter().all(char::is_ascii_digit) {
+        return None;
+    }
+    // Too many edits to even parse are rejected with the rest
+    let edits = (!edits.is_empty()).then(|| {
+        edits
+            .iter()
+            .collect::<String>()
+            .parse()
+            .unwrap_or(usize::MAX)
+    });
+    Some((base, edits))
+}
+
+/// Split the query into tokens, each with the position it starts at
+fn tokenize(query: &[char]) -> Vec<(usize, QueryToken)> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < query.len() {
+        let start = i;
+        let token = match query[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => QueryToken::Open,
+            ')' => QueryToken::Close,
+            // A missing closing quote ends the phrase at the end of the query
+            '"' => {
+                let len = query[i + 1..].iter().position(|c| *c == '"');
+                let end = len.map_or(query.len(), |len| i + 1 + len);
+                let phrase = Lexer::new(&query[i + 1..end]).collect();
+                i = end;
+                QueryToken::Phrase(phrase)
+            }
+            // Modifiers apply to words, phrases and groups alike
+            '+' | '-'
+                if query
+                    .get(i + 1)
+                    .is_some_and(|c| !c.is_whitespace() && *c != ')') =>
+            {
+                if query[i] == '+' {
+                    QueryToken::Plus
+                } else {
+                    QueryToken::Minus
+                }
+            }
+            _ => {
+                let len = query[i..].iter().position(|c| is_word_end(*c));
+                let end = len.map_or(query.len(), |len| i + len);
+                let word = &query[i..end];
+                i = end - 1;
+                match word.iter().collect::<String>().as_str() {
+                    "AND" => QueryToken::And,
+                    "OR" => QueryToken::Or,
+                    "NOT" => QueryToken::Not,
+                    // Patterns aren't stemmed, they're meant to match stems
+                    word if word.contains(is_wildcard) => {
+                        QueryToken::Pattern(word.to_ascii_lowercase())
+                    }
+                    _ => match split_fuzzy(word) {
+                        Some((base, edits)) => QueryToken::Fuzzy(Lexer::new(base).collect(), edits),
+                        None => QueryToken::Word(Lexer::new(word).collect()),
+                    },
+                }
+            }
+        };
+        tokens.push((start, token));
+        i += 1;
+    }
+    tokens
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Modifier {
+    Required,
+    Excluded,
+}
+
+struct Operand {
+    modifier: Option<Modifier>,
+    node: Node,
+    position: usize,
+}
+
+struct Parser {
+    tokens: Vec<(usize, QueryToken)>,
+    next: usize,
+    /// Position of the end of the query
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&QueryToken> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(pos, _)| *pos)
+    }
+
+    fn eat(&mut self, token: QueryToken) -> bool {
+        if self.peek() == Some(&token) {
+            self.next += 1;
+            return true;
+        }
+        false
+    }
+
+    /// Juxtaposed queries, up to the end of the query or of the group
+    fn parse_sequence(&mut self) -> Result<Node, QueryError> {
+        let start = self.position();
+        let (mut must, mut should, mut must_not) = (Vec::new(), Vec::new(), Vec::new());
+        while self.peek().is_some_and(|token| *token != QueryToken::Close) {
+            let operand = self.parse_or()?;
+            match operand.modifier {
+                Some(Modifier::Required) => must.push(operand.node),
+                Some(Modifier::Excluded) => must_not.push(operand.node),
+                None => should.push(operand.node),
+            }
+        }
+        if must.is_empty() && should.is_empty() {
+            let message = if must_not.is_empty() {
+                "nothing to search for"
+            } else {
+                "nothing to search for besides excluded terms"
+            };
+            return Err(QueryError::new(message, start));
+        }
+        if must.len() + should.len() == 1 && must_not.is_empty() {
+            return Ok(must.pop().or(should.pop()).expect("one node is left"));
+        }
+        Ok(Node::Bool {
+            must,
+            should,
+            must_not,
+        })
+    }
+
+    fn parse_or(&mut self) -> Result<Operand, QueryError> {
+        let first = self.parse_and()?;
+        if self.peek() != Some(&QueryToken::Or) {
+            return Ok(first);
+        }
+        let position = first.position;
+        let mut should = Vec::new();
+        let mut operand = first;
+        loop {
+            match operand.modifier {
+                Some(Modifier::Excluded) => {
+                    return Err(QueryError::new(
+                        "excluded terms can't be alternatives of OR",
+                        operand.position,
+                    ));
+                }
+                // Only one of the alternatives has to match
+                Some(Modifier::Required) => {
+                    return Err(QueryError::new(
+                        "required terms can't be alternatives of OR",
+                        operand.position,
+                    ));
+                }
+                None => {}
+            }
+            should.push(operand.node);
+            if !self.eat(QueryToken::Or) {
+                break;
+            }
+            operand = self.parse_and()?;
+        }
+        Ok(Operand {
+            modifier: None,
+            node: Node::Bool {
+                must: Vec::new(),
+                should,
+                must_not: Vec::new(),
+            },
+            position,
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Operand, QueryError> {
+        let first = self.parse_unary()?;
+        if self.peek() != Some(&QueryToken::And) {
+            return Ok(first);
+        }
+        let position = first.position;
+        let (mut must, mut must_not) = (Vec::new(), Vec::new());
+        let mut operand = first;
+        loop {
+            match operand.modifier {
+                Some(Modifier::Excluded) => must_not.push(operand.node),
+                _ => must.push(operand.node),
+            }
+            if !self.eat(QueryToken::And) {
+                break;
+            }
+            operand = self.parse_unary()?;
+        }
+        if must.is_empty() {
+            return Err(QueryError::new(
+                "nothing to search for besides excluded terms",
+                position,
+            ));
+        }
+        Ok(Operand {
+            modifier: None,
+            node: Node::Bool {
+                must,
+                should: Vec::new(),
+                must_not,
+            },
+            position,
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<Operand, QueryError> {
+        let position = self.position();
+        let modifier = match self.peek() {
+            Some(QueryToken::Not | QueryToken::Minus) => Some(Modifier::Excluded),
+            Some(QueryToken::Plus) => Some(Modifier::Required),
+            _ => None,
+        };
+        if modifier.is_some() {
+            self.next += 1;
+        }
+        Ok(Operand {
+            modifier,
+            node: self.parse_primary()?,
+            position,
+        })
+    }
+
+    fn parse_primary(&mut self) -> Result<Node, QueryError> {
+        let position = self.position();
+        let Some((_, token)) = self.tokens.get_mut(self.next) else {
+            return Err(QueryError::new("expected a term", position));
+        };
+        let node = match token {
+            QueryToken::Open => {
+                self.next += 1;
+                let node = self.parse_sequence()?;
+                if !self.eat(QueryToken::Close) {
+                    return Err(QueryError::new("missing closing parenthesis", position));
+                }
+                return Ok(node);
+            }
+            QueryToken::Phrase(terms) | QueryToken::Word(terms) if terms.len() == 1 => {
+                Node::Term(terms.remove(0))
+            }
+            QueryToken::Phrase(terms) if terms.is_empty() => {
+                return Err(QueryError::new("empty phrase", position));
+            }
+            QueryToken::Phrase(terms) => Node::Phrase(std::mem::take(terms)),
+            QueryToken::Pattern(pattern) if pattern.starts_with(is_wildcard) => {
+                return Err(QueryError::new(
+                    "patterns can't start with a wildcard",
+                    position,
+                ));
+            }
+            QueryToken::Pattern(pattern) => Node::Pattern(std::mem::take(pattern)),
+            QueryToken::Fuzzy(terms, _) if terms.len() != 1 => {
+                return Err(QueryError::new(
+                    "fuzzy terms must be a single word",
+                    position,
+                ));
+            }
+            QueryToken::Fuzzy(_, Some(edits)) if *edits > MAX_EDITS => {
+                return Err(QueryError::new(
+                    format!("fuzzy terms allow at most {MAX_EDITS} edits"),
+                    position,
+                ));
+            }
+            QueryToken::Fuzzy(terms, edits) => {
+                let term = terms.remove(0);
+                let max_edits = edits.unwrap_or_else(|| auto_edits(&term));
+                Node::Fuzzy { term, max_edits }
+            }
+            // Punctuation splits a word into several terms, any of them
+            // may match like separate words would
+            QueryToken::Word(terms) => Node::Bool {
+                must: Vec::new(),
+                should: std::mem::take(terms).into_iter().map(Node::Term).collect(),
+                must_not: Vec::new(),
+            },
+            QueryToken::Close => {
+                return Err(QueryError::new("unexpected closing parenthesis", position))
+            }
+            QueryToken::And | QueryToken::Or => {
+                return Err(QueryError::new(
+                    "AND and OR need a term on both sides",
+                    position,
+                ));
+            }
+            QueryToken::Not | QueryToken::Plus | QueryToken::Minus => {
+                return Err(QueryError::new("expected a term", position));
+            }
+        };
+        self.next += 1;
+        Ok(node)
+    }
+}
+
+/// Words of the query standing for a single term, with the range of the
+/// query they cover. Operators, phrases and patterns are left out.
+pub fn query_words(query: &[char]) -> Vec<(Range<usize>, String)> {
+    tokenize(query)
+        .into_iter()
+        .filter_map(|(start, token)| match token {
+            QueryToken::Word(mut terms) if terms.len() == 1 => {
+                let len = query[start..].iter().position(|c| is_word_end(*c));
+                let end = len.map_or(query.len(), |len| start + len);
+                Some((start..end, terms.remove(0)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn parse_query(query: &[char]) -> Result<Node, QueryError> {
+    let mut parser = Parser {
+        tokens: tokenize(query),
+        next: 0,
+        end: query.len(),
+    };
+    let node = parser.parse_sequence()?;
+    if parser.peek().is_some() {
+        return Err(QueryError::new(
+            "unexpected closing parenthesis",
+            parser.position(),
+        ));
+    }
+    Ok(node)
+}
+
+/// Width and positions of every term and phrase each document matched
+pub type Matches = HashMap<DocId, Vec<(usize, Vec<usize>)>>;
+
+/// Evaluates a parsed query against an index
+pub struct Search<'a> {
+    index: &'a dyn IndexReader,
+    scorer: &'a dyn Scorer,
+    corpus: CorpusStats,
+    /// Whether to collect `matches`
+    positions: bool,
+    /// Whether terms missing from the index are looked up fuzzily
+    fuzzy: bool,
+    /// Width and positions of every term and phrase each document matched,
+    /// excluded ones aside
+    pub matches: Matches,
+    /// Terms and phrases already in `matches`
+    matched: Vec<&'a Node>,
+}
+
+impl<'a> Search<'a> {
+    pub fn new(
+        index: &'a dyn IndexReader,
+        scorer: &'a dyn Scorer,
+        options: &SearchOptions,
+    ) -> Self {
+        Self {
+            index,
+            scorer,
+            corpus: index.corpus_stats(),
+            positions: options.proximity > 0.0 || options.matches,
+            fuzzy: options.fuzzy,
+            matches: HashMap::new(),
+            matched: Vec::new(),
+        }
+    }
+
+    /// Score of every document matching `node`
+    pub fn evaluate(&mut self, node: &'a Node) -> Result<HashMap<DocId, f32>> {
+        self.evaluate_node(node, true)
+    }
+
+    fn evaluate_node(&mut self, node: &'a Node, positive: bool) -> Result<HashMap<DocId, f32>> {
+        let (must, should, must_not) = match node {
+            Node::Term(_) | Node::Phrase(_) | Node::Pattern(_) | Node::Fuzzy { .. } => {
+                return self.evaluate_leaf(node, positive)
+            }
+            Node::Bool {
+                must,
+                should,
+                must_not,
+            } => (must, should, must_not),
+        };
+
+        let mut scores: Option<HashMap<DocId, f32>> = None;
+        for node in must {
+            let matches = self.evaluate_node(node, positive)?;
+            scores = Some(match scores {
+                None => matches,
+                Some(mut scores) => {
+                    scores.retain(|doc, _| matches.contains_key(doc));
+                    for (doc, score) in scores.iter_mut() {
+                        *score += matches[doc];
+                    }
+                    scores
+                }
+            });
+        }
+        let required = scores.is_some();
+        let mut scores = scores.unwrap_or_default();
+        for node in should {
+            for (doc, score) in self.evaluate_node(node, positive)? {
+                if let Some(total) = scores.get_mut(&doc) {
+                    *total += score;
+                } else if !required {
+                    scores.insert(doc, score);
+                }
+            }
+        }
+        for node in must_not {
+            for doc in self.evaluate_node(node, false)?.keys() {
+                scores.remove(doc);
+            }
+        }
+        Ok(scores)
+    }
+
+    fn evaluate_leaf(&mut self, node: &'a Node, positive: bool) -> Result<HashMap<DocId, f32>> {
+        let (postings, width) = match node {
+            Node::Term(term) => match self.index.postings(term)? {
+                // Most likely a typo
+                postings if postings.is_empty() && self.fuzzy => {
+                    let max_edits = auto_edits(term);
+                    (Cow::Owned(fuzzy_postings(self.index, term, max_edits)?), 1)
+                }
+                postings => (postings, 1),
+            },
+            Node::Phrase(terms) => (Cow::Owned(phrase_postings(self.index, terms)?), terms.len()),
+            Node::Pattern(pattern) => (Cow::Owned(pattern_postings(self.index, pattern)?), 1),
+            Node::Fuzzy { term, max_edits } => {
+                (Cow::Owned(fuzzy_postings(self.index, term, *max_edits)?), 1)
+            }
+            Node::Bool { .. } => unreachable!("only terms, phrases and patterns are leaves"),
+        };
+        // A repeated term is as close as can be to itself
+        if self.positions && positive && !self.matched.contains(&node) {
+            self.matched.push(node);
+            for posting in postings.iter() {
+                let doc_matches = self.matches.entry(posting.doc).or_default();
+                doc_matches.push((width, posting.positions.clone()));
+            }
+        }
+        let mut scores = HashMap::with_capacity(postings.len());
+        for posting in postings.iter() {
+            let term = TermStats {
+                tf: posting.tf,
+                df: postings.len(),
+            };
+            let doc = self.index.doc_stats(posting.doc)?;
+            scores.insert(posting.doc, self.scorer.score(&term, &doc, &self.corpus));
+        }
+        Ok(scores)
+    }
+}
+
+/// Postings of the documents where `terms` occur one after the other, with
+/// the positions the phrase starts at
+pub fn phrase_postings(index: &dyn IndexReader, terms: &[String]) -> Result<Vec<Posting>> {
+    let mut matches = index.postings(&terms[0])?.into_owned();
+    for (offset, term) in terms.iter().enumerate().skip(1) {
+        if matches.is_empty() {
+            break;
+        }
+        let postings = index.postings(term)?;
+        // Both lists are sorted by document
+        let mut next = postings.iter().peekable();
+        matches.retain_mut(|posting| {
+            while next.next_if(|other| other.doc < posting.doc).is_some() {}
+            let Some(other) = next.next_if(|other| other.doc == posting.doc) else {
+                return false;
+            };
+            posting
+                .positions
+                .retain(|start| other.positions.binary_search(&(start + offset)).is_ok());
+            posting.tf = posting.positions.len();
+            posting.tf > 0
+        });
+    }
+    Ok(matches)
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any characters and
+/// `?` a single one
+fn wildcard_match(pattern: &[char], text: &[char]) -> bool {
+    // Position of the last `*` seen and of the text it has consumed up to
+    let mut star = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Postings of the terms matching `pattern`, merged as if they were a
+/// single term
+fn pattern_postings(index: &dyn IndexReader, pattern: &str) -> Result<Vec<Posting>> {
+    let prefix = &pattern[..pattern.find(is_wildcard).unwrap_or(pattern.len())];
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let mut terms = Vec::new();
+    index.visit_terms(prefix, &mut |term| {
+        if wildcard_match(&pattern, &term.chars().collect::<Vec<_>>()) {
+            terms.push(term.to_string());
+        }
+        terms.len() < MAX_EXPANSIONS
+    })?;
+    merge_postings(index, &terms)
+}
+
+/// Postings of the terms within `max_edits` of `term`, merged as if they
+/// were a single term
+fn fuzzy_postings(index: &dyn IndexReader, term: &str, max_edits: usize) -> Result<Vec<Posting>> {
+    let terms = fuzzy_terms(index, term, max_edits, MAX_EXPANSIONS)?
+        .into_iter()
+        .map(|(term, _)| term)
+        .collect::<Vec<_>>();
+    merge_postings(index, &terms)
+}
+
+fn merge_postings(index: &dyn IndexReader, terms: &[String]) -> Result<Vec<Posting>> {
+    let mut merged = BTreeMap::<DocId, Posting>::new();
+    for term in terms {
+        for posting in index.postings(term)?.iter() {
+            let doc = merged.entry(posting.doc).or_insert(Posting {
+                doc: posting.doc,
+                tf: 0,
+                positions: Vec::new(),
+                forms: Vec::new(),
+            });
+            doc.tf += posting.tf;
+            doc.positions.extend(&posting.positions);
+        }
+    }
+    Ok(merged
+        .into_values()
+        .map(|mut posting| {
+            posting.positions.sort_unstable();
+            posting
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> Result<Node, QueryError> {
+        parse_query(&query.chars().collect::<Vec<_>>())
+    }
+
+    fn term(term: &str) -> Node {
+        Node::Term(term.to_string())
+    }
+
+    fn bool_node(must: Vec<Node>, should: Vec<Node>, must_not: Vec<Node>) -> Node {
+        Node::Bool {
+            must,
+            should,
+            must_not,
+        }
+    }
+
+    fn error(query: &str) -> (String, usize) {
+        let err = parse(query).expect_err("query is invalid");
+        (err.message, err.position)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let and = bool_node(vec![term("b"), term("c")], vec![], vec![]);
+        let expected = bool_node(vec![], vec![term("a"), and], vec![]);
+        assert_eq!(parse("a OR b AND c").unwrap(), expected);
+    }
+
+    #[test]
+    fn or_binds_tighter_than_juxtaposition() {
+        let or = bool_node(vec![], vec![term("b"), term("c")], vec![]);
+        let expected = bool_node(vec![], vec![term("a"), or], vec![]);
+        assert_eq!(parse("a b OR c").unwrap(), expected);
+    }
+
+    #[test]
+    fn modifiers_and_groups() {
+        let group = bool_node(vec![], vec![term("b"), term("c")], vec![]);
+        let expected = bool_node(vec![term("a")], vec![term("d")], vec![group]);
+        assert_eq!(parse("+a -(b OR c) d").unwrap(), expected);
+        let expected = bool_node(vec![], vec![term("a")], vec![term("b")]);
+        assert_eq!(parse("a NOT b").unwrap(), expected);
+    }
+
+    #[test]
+    fn unclosed_quote_ends_the_phrase_at_the_end() {
+        let expected = Node::Phrase(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(parse("\"a b").unwrap(), expected);
+        assert_eq!(error("a \""), ("empty phrase".to_string(), 2));
+    }
+
+    #[test]
+    fn unbalanced_parentheses() {
+        let missing = "missing closing parenthesis".to_string();
+        assert_eq!(error("a (b c"), (missing, 2));
+        let unexpected = "unexpected closing parenthesis".to_string();
+        assert_eq!(error("a b)"), (unexpected, 3));
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error("a AND"), ("expected a term".to_string(), 5));
+        assert_eq!(
+            error("a OR AND b"),
+            ("AND and OR need a term on both sides".to_string(), 5)
+        );
+        assert_eq!(
+            error("a OR -b"),
+            ("excluded terms can't be alternatives of OR".to_string(), 5)
+        );
+        assert_eq!(
+            error("+a OR b"),
+            ("required terms can't be alternatives of OR".to_string(), 0)
+        );
+        assert_eq!(
+            error("-a -b"),
+            (
+                "nothing to search for besides excluded terms".to_string(),
+                0
+            )
+        );
+        assert_eq!(
+            error("x *a"),
+            ("patterns can't start with a wildcard".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn fuzzy_terms() {
+        let fuzzy = |term: &str, max_edits| Node::Fuzzy {
+            term: term.to_string(),
+            max_edits,
+        };
+        assert_eq!(parse("abc~1").unwrap(), fuzzy("abc", 1));
+        assert_eq!(parse("abc~").unwrap(), fuzzy("abc", 1));
+        assert_eq!(parse("abcdef~").unwrap(), fuzzy("abcdef", 2));
+        assert_eq!(
+            error("a abc~3"),
+            ("fuzzy terms allow at most 2 edits".to_string(), 2)
+        );
+        assert_eq!(
+            error("a-b~1"),
+            ("fuzzy terms must be a single word".to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn split_fuzzy_words() {
+        let chars = |word: &str| word.chars().collect::<Vec<_>>();
+        let word = chars("ab~2");
+        assert_eq!(split_fuzzy(&word), Some((&word[..2], Some(2))));
+        let word = chars("ab~");
+        assert_eq!(split_fuzzy(&word), Some((&word[..2], None)));
+        assert_eq!(
+            split_fuzzy(&chars("ab~99999999999999999999")).unwrap().1,
+            Some(usize::MAX)
+        );
+        assert_eq!(split_fuzzy(&chars("~1")), None);
+        assert_eq!(split_fuzzy(&chars("ab~x")), None);
+        assert_eq!(split_fuzzy(&chars("ab")), None);
+    }
+
+    #[test]
+    fn tokenize_operators_and_words() {
+        let tokens = tokenize(&"(a OR -b) +c - d".chars().collect::<Vec<_>>());
+        let words = |word: &str| QueryToken::Word(vec![word.to_string()]);
+        let expected = vec![
+            (0, QueryToken::Open),
+            (1, words("a")),
+            (3, QueryToken::Or),
+            (6, QueryToken::Minus),
+            (7, words("b")),
+            (8, QueryToken::Close),
+            (10, QueryToken::Plus),
+            (11, words("c")),
+            (13, words("-")),
+            (15, words("d")),
+        ];
+        assert!(tokens == expected);
+    }
+
+    #[test]
+    fn wildcards_backtrack() {
+        let matches = |pattern: &str, text: &str| {
+            let pattern = pattern.chars().collect::<Vec<_>>();
+            wildcard_match(&pattern, &text.chars().collect::<Vec<_>>())
+        };
+        assert!(matches("a*", "a"));
+        assert!(matches("a*", "abc"));
+        assert!(matches("a*c", "abcbc"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(matches("a?c", "abc"));
+        assert!(matches("a**", "ab"));
+        assert!(matches("a*?", "ab"));
+        assert!(!matches("a*?", "a"));
+        assert!(!matches("a?c", "ac"));
+        assert!(!matches("a*c", "abcb"));
+        assert!(!matches("abc", "ab"));
+    }
+}
@@ -0,0 +1,258 @@
+/// Statistics of a query term within a single document
+pub struct TermStats {
+    /// Occurrences of the term in the document
+    pub tf: usize,
+    /// Number of documents containing the term
+    pub df: usize,
+}
+
+/// Statistics of the document being scored
+pub struct DocStats {
+    /// Number of tokens in the document
+    pub length: usize,
+    /// Euclidean norm of the document's log-weighted term vector
+    pub norm: f32,
+}
+
+/// Statistics of the whole indexed corpus
+pub struct CorpusStats {
+    pub doc_count: usize,
+    pub avg_doc_length: f32,
+}
+
+/// A ranking function. The score of a document for a query is the sum of
+/// `score` over every query term the document contains.
+pub trait Scorer {
+    fn score(&self, term: &TermStats, doc: &DocStats, corpus: &CorpusStats) -> f32;
+}
+
+/// Settings ranking functions may be tuned with, `None` for the default of
+/// the function. Functions ignore the settings they have no use for.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ScorerParams {
+    /// BM25 term frequency saturation
+    pub k1: Option<f32>,
+    /// BM25 document length normalization
+    pub b: Option<f32>,
+}
+
+/// Builds a ranking function from the settings of a query
+pub type ScorerFactory = fn(&ScorerParams) -> Box<dyn Scorer>;
+
+/// Name of the ranking function queries use unless they ask for another
+pub const DEFAULT_RANKING: &str = "tfidf";
+
+/// The ranking functions queries can ask for by name
+pub struct Scorers {
+    scorers: Vec<(&'static str, ScorerFactory)>,
+}
+
+impl Default for Scorers {
+    fn default() -> Self {
+        let mut scorers = Self {
+            scorers: Vec::new(),
+        };
+        scorers.register("tfidf", |_| Box::new(TfIdf));
+        scorers.register("tf-idf", |_| Box::new(TfIdf));
+        scorers.register("bm25", |params| {
+            Box::new(Bm25 {
+                k1: params.k1.unwrap_or(Bm25::K1),
+                b: params.b.unwrap_or(Bm25::B),
+            })
+        });
+        scorers.register("cosine", |_| Box::new(Cosine));
+        scorers
+    }
+}
+
+impl Scorers {
+    /// Make the ranking function built by `factory` available as `name`,
+    /// in place of any registered under the same name
+    pub fn register(&mut self, name: &'static str, factory: ScorerFactory) {
+        self.scorers.retain(|(other, _)| *other != name);
+        self.scorers.push((name, factory));
+    }
+
+    /// The ranking function called `name` set up with `params`
+    pub fn get(&self, name: &str, params: &ScorerParams) -> Option<Box<dyn Scorer>> {
+        self.scorers
+            .iter()
+            .find(|(other, _)| *other == name)
+            .map(|(_, factory)| factory(params))
+    }
+}
+
+/// Term frequency times a smoothed idf that stays positive for terms in
+/// every document, so more occurrences always rank a document higher
+pub struct TfIdf;
+
+impl Scorer for TfIdf {
+    fn score(&self, term: &TermStats, doc: &DocStats, corpus: &CorpusStats) -> f32 {
+        let tf = term.tf as f32 / doc.length as f32;
+        let idf = f32::ln(1.0 + corpus.doc_count as f32 / term.df.max(1) as f32);
+        tf * idf
+    }
+}
+
+/// Okapi BM25 with term frequency saturation `k1` and length normalization `b`
+pub struct Bm25 {
+    pub k1: f32,
+    pub b: f32,
+}
+
+impl Bm25 {
+    pub const K1: f32 = 1.2;
+    pub const B: f32 = 0.75;
+}
+
+impl Default for Bm25 {
+    fn default() -> Self {
+        Self {
+            k1: Self::K1,
+            b: Self::B,
+        }
+    }
+}
+
+impl Scorer for Bm25 {
+    fn score(&self, term: &TermStats, doc: &DocStats, corpus: &CorpusStats) -> f32 {
+        let tf = term.tf as f32;
+        let norm = 1.0 - self.b + self.b * doc.length as f32 / corpus.avg_doc_length;
+        let n = corpus.doc_count as f32;
+        let m = term.df as f32;
+        let idf = f32::ln((n - m + 0.5) / (m + 0.5) + 1.0);
+        tf * (self.k1 + 1.0) / (tf + self.k1 * norm) * idf
+    }
+}
+
+/// Vector space cosine similarity (SMART lnc.ltc): log-weighted document
+/// vectors normalized to unit length against idf-weighted query terms.
+pub struct Cosine;
+
+/// Weight of a term occurring `tf` times in a document vector
+pub fn log_tf(tf: usize) -> f32 {
+    1.0 + f32::ln(tf as f32)
+}
+
+impl Scorer for Cosine {
+    fn score(&self, term: &TermStats, doc: &DocStats, corpus: &CorpusStats) -> f32 {
+        let idf = f32::ln(corpus.doc_count as f32 / term.df as f32);
+        log_tf(term.tf) / doc.norm * idf
+    }
+}
+
+/// How strongly proximity boosts scores unless a query asks otherwise
+pub const PROXIMITY_WEIGHT: f32 = 0.5;
+
+/// How close together the query terms occur in a document, from 0 when
+/// fewer than two of them occur up to 1 when they are next to each other.
+/// Every matched term comes with its width in tokens, 1 unless it's a
+/// phrase, and the positions it occurs at.
+pub fn proximity(matched: &[(usize, &[usize])]) -> f32 {
+    if matched.len() < 2 {
+        return 0.0;
+    }
+    let mut occurrences = matched
+        .iter()
+        .enumerate()
+        .flat_map(|(term, (_, positions))| positions.iter().map(move |&pos| (pos, term)))
+        .collect::<Vec<_>>();
+    occurrences.sort_unstable();
+
+    // Smallest window containing every term at least once
+    let mut counts = vec![0; matched.len()];
+    let mut covered = 0;
+    let mut span = usize::MAX;
+    let mut start = 0;
+    for &(pos, term) in &occurrences {
+        if counts[term] == 0 {
+            covered += 1;
+        }
+        counts[term] += 1;
+        while covered == matched.len() {
+            let (first, first_term) = occurrences[start];
+            span = span.min(pos + matched[term].0 - first);
+            counts[first_term] -= 1;
+            if counts[first_term] == 0 {
+                covered -= 1;
+            }
+            start += 1;
+        }
+    }
+    let shortest: usize = matched.iter().map(|(width, _)| width).sum();
+    shortest as f32 / span.max(shortest) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Constant;
+
+    impl Scorer for Constant {
+        fn score(&self, _: &TermStats, _: &DocStats, _: &CorpusStats) -> f32 {
+            1.0
+        }
+    }
+
+    fn score(scorer: &dyn Scorer) -> f32 {
+        let term = TermStats { tf: 2, df: 1 };
+        let doc = DocStats {
+            length: 10,
+            norm: 1.0,
+        };
+        let corpus = CorpusStats {
+            doc_count: 4,
+            avg_doc_length: 5.0,
+        };
+        scorer.score(&term, &doc, &corpus)
+    }
+
+    #[test]
+    fn tf_idf_ranks_by_tf_for_terms_in_every_document() {
+        let corpus = CorpusStats {
+            doc_count: 3,
+            avg_doc_length: 10.0,
+        };
+        let doc = DocStats {
+            length: 10,
+            norm: 1.0,
+        };
+        let score = |tf| TfIdf.score(&TermStats { tf, df: 3 }, &doc, &corpus);
+        assert!(score(1) > 0.0);
+        assert!(score(2) > score(1));
+    }
+
+    #[test]
+    fn scorers_by_name() {
+        let mut scorers = Scorers::default();
+        let params = ScorerParams::default();
+        assert!(scorers.get("constant", &params).is_none());
+        scorers.register("constant", |_| Box::new(Constant));
+        assert_eq!(
+            score(scorers.get("constant", &params).unwrap().as_ref()),
+            1.0
+        );
+        scorers.register("tfidf", |_| Box::new(Constant));
+        assert_eq!(score(scorers.get("tfidf", &params).unwrap().as_ref()), 1.0);
+    }
+
+    #[test]
+    fn scorer_params_override_the_defaults() {
+        let scorers = Scorers::default();
+        let bm25 = |params| score(scorers.get("bm25", &params).unwrap().as_ref());
+        let default = Bm25::default();
+        assert_eq!(bm25(ScorerParams::default()), score(&default));
+        let tuned = ScorerParams {
+            k1: Some(2.0),
+            b: None,
+        };
+        assert_eq!(
+            bm25(tuned),
+            score(&Bm25 {
+                k1: 2.0,
+                b: Bm25::B
+            })
+        );
+    }
+}
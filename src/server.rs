@@ -1,9 +1,31 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use serde::Serialize;
 use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
 
+use crate::archive::*;
+use crate::error::*;
 use crate::model::*;
+use crate::parser::*;
+use crate::scorer::*;
+use crate::snippet::*;
+use crate::suggest::*;
+
+fn respond<R: Read>(request: Request, response: Response<R>) -> Result<()> {
+    let url = request.url().to_string();
+    request.respond(response).map_err(|err| Error::Http {
+        context: format!("could not respond to {url}"),
+        reason: err.to_string(),
+    })
+}
 
-fn serve_static_file(request: Request, file_path: &str, content_type: &str) -> Result<(), ()> {
+fn serve_static_file(request: Request, file_path: &str, content_type: &str) -> Result<()> {
     println!(
         "Info: received request! method: {:?}, url{:?}",
         request.method(),
@@ -11,66 +33,382 @@ fn serve_static_file(request: Request, file_path: &str, content_type: &str) -> R
     );
     let content_type_header = Header::from_bytes("Content-Type", content_type)
         .expect("Didn't make an error in the header");
-    let file_path = File::open(file_path).map_err(|err| {
-        eprintln!("Error: could not serve file {file_path}; {err}");
-    })?;
-    let response = Response::from_file(file_path).with_header(content_type_header);
-    request.respond(response).map_err(|err| {
-        eprintln!("Error: could not serve a request; {err}");
-    })
+    let file = match File::open(file_path) {
+        Ok(file) => file,
+        Err(err) => {
+            return serve_error(
+                request,
+                Error::io(format!("could not serve file {file_path}"), err),
+            )
+        }
+    };
+    respond(
+        request,
+        Response::from_file(file).with_header(content_type_header),
+    )
+}
+
+fn serve_404(request: Request) -> Result<()> {
+    respond(
+        request,
+        Response::from_string("404").with_status_code(StatusCode(404)),
+    )
 }
 
-fn serve_404(request: Request) -> Result<(), ()> {
-    request
-        .respond(Response::from_string("404").with_status_code(StatusCode(404)))
-        .map_err(|err| {
-            eprintln!("Error: could not serve request; {err}");
+fn serve_500(request: Request) -> Result<()> {
+    respond(
+        request,
+        Response::from_string("500").with_status_code(StatusCode(500)),
+    )
+}
+
+fn serve_400(request: Request, message: &str) -> Result<()> {
+    respond(
+        request,
+        Response::from_string(message).with_status_code(StatusCode(400)),
+    )
+}
+
+/// Answer with the status matching `err`. Invalid queries are the
+/// client's fault, anything else is reported back to the caller.
+fn serve_error(request: Request, err: Error) -> Result<()> {
+    match err {
+        Error::Query(err) => {
+            let json = serde_json::json!({ "error": "invalid query", "message": err.message, "position": err.position });
+            let content_type_header = Header::from_bytes("Content-Type", "application/json")
+                .expect("No garbage in headers");
+            let response = Response::from_string(json.to_string())
+                .with_status_code(StatusCode(400))
+                .with_header(content_type_header);
+            respond(request, response)
+        }
+        err => {
+            serve_500(request)?;
+            Err(err)
+        }
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let hex = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Split a request url into its path and decoded query parameters
+fn parse_url(url: &str) -> (&str, HashMap<String, String>) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect();
+    (path, params)
+}
+
+fn parse_param<T>(params: &HashMap<String, String>, key: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    params
+        .get(key)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|err| format!("invalid value {value} for {key}; {err}"))
         })
+        .transpose()
+}
+
+fn scorer_from_params(
+    params: &HashMap<String, String>,
+    scorers: &Scorers,
+) -> Result<Box<dyn Scorer>, String> {
+    let name = params
+        .get("ranking")
+        .map_or(DEFAULT_RANKING, |name| name.as_str());
+    let scorer_params = ScorerParams {
+        k1: parse_param(params, "k1")?,
+        b: parse_param(params, "b")?,
+    };
+    scorers
+        .get(name, &scorer_params)
+        .ok_or_else(|| format!("unknown ranking function {name}"))
+}
+
+fn options_from_params(params: &HashMap<String, String>) -> Result<SearchOptions, String> {
+    let proximity = match parse_param(params, "proximity")? {
+        Some(weight) if weight < 0.0 => return Err(format!("negative proximity weight {weight}")),
+        weight => weight.unwrap_or(PROXIMITY_WEIGHT),
+    };
+    Ok(SearchOptions {
+        proximity,
+        fuzzy: parse_param(params, "fuzzy")?.unwrap_or(false),
+        matches: parse_param(params, "snippets")?.unwrap_or(false),
+    })
+}
+
+#[derive(Serialize)]
+struct Hit {
+    #[serde(skip)]
+    doc: DocId,
+    path: PathBuf,
+    score: f32,
+    /// HTML fragments of the document around the matches, only when asked
+    /// for with `snippets=true`
+    snippets: Vec<String>,
 }
 
-fn serve_500(request: Request) -> Result<(), ()> {
-    request
-        .respond(Response::from_string("505").with_status_code(StatusCode(505)))
-        .map_err(|err| {
-            eprintln!("Error: could not serve request; {err}");
+/// Hits for the first `limit` ranked documents, without snippets
+fn resolve_hits(
+    index: &dyn IndexReader,
+    results: &SearchResults,
+    limit: usize,
+) -> Result<Vec<Hit>> {
+    let paths = resolve_paths(index, &results.rank, limit)?;
+    Ok(paths
+        .into_iter()
+        .zip(&results.rank)
+        .map(|((path, score), (doc, _))| Hit {
+            doc: *doc,
+            path,
+            score,
+            snippets: Vec::new(),
         })
+        .collect())
+}
+
+/// Cut snippets out of the documents of `hits` at `locations`. Documents
+/// that can't be read anymore are still listed, without snippets.
+fn add_snippets(
+    hits: &mut [Hit],
+    locations: &[(PathBuf, Option<String>)],
+    results: &SearchResults,
+) {
+    for (hit, text) in hits.iter_mut().zip(document_texts(locations)) {
+        match text {
+            Ok(text) => {
+                let matches = results.matches.get(&hit.doc).map_or(&[][..], Vec::as_slice);
+                hit.snippets = snippets(&text, matches);
+            }
+            Err(err) => eprintln!("Error: {err}"),
+        }
+    }
 }
 
-fn serve_search(mut request: Request, model: &Model) -> Result<(), ()> {
+#[derive(Serialize)]
+struct SearchResponse {
+    results: Vec<Hit>,
+    /// The query with misspelled words corrected, if any
+    suggestion: Option<String>,
+}
+
+fn serve_search(
+    mut request: Request,
+    params: &HashMap<String, String>,
+    index: &RwLock<impl IndexReader>,
+    scorers: &Scorers,
+) -> Result<()> {
+    let scorer = match scorer_from_params(params, scorers) {
+        Ok(scorer) => scorer,
+        Err(message) => return serve_400(request, &message),
+    };
+    let options = match options_from_params(params) {
+        Ok(options) => options,
+        Err(message) => return serve_400(request, &message),
+    };
+
     let mut query = String::new();
-    request.as_reader().read_to_string(&mut query).unwrap();
+    if let Err(err) = request.as_reader().read_to_string(&mut query) {
+        return serve_400(request, &format!("could not read query; {err}"));
+    }
     let query = query.chars().collect::<Vec<_>>();
 
-    let rank = search_query(&query, &model);
-    let json = match serde_json::to_string(&rank.iter().take(20).collect::<Vec<_>>()) {
-        Ok(json) => json,
-        Err(err) => {
-            eprintln!("Error: could not convert search results to JSON; {err}");
-            return serve_500(request);
-        }
+    let found = {
+        let index = index.read().unwrap();
+        search_query(&query, &*index, scorer.as_ref(), &options).and_then(|results| {
+            let hits = resolve_hits(&*index, &results, 20)?;
+            let mut locations = Vec::new();
+            if options.matches {
+                for hit in &hits {
+                    locations.push(index.doc_location(hit.doc)?);
+                }
+            }
+            Ok((results, hits, locations, suggest_query(&query, &*index)?))
+        })
     };
+    let (results, mut hits, locations, suggestion) = match found {
+        Ok(found) => found,
+        Err(err) => return serve_error(request, err),
+    };
+    // Documents are read without holding up index updates
+    add_snippets(&mut hits, &locations, &results);
+    let response = SearchResponse {
+        results: hits,
+        suggestion,
+    };
+    let json = serde_json::to_string(&response).expect("hits serialize");
     let content_type_header =
         Header::from_bytes("Content-type", "application/json").expect("No garbage in headers");
-    request
-        .respond(Response::from_string(&json).with_header(content_type_header))
-        .map_err(|err| {
-            eprintln!("Error: Could not serve search; {err}");
-        })
+    respond(
+        request,
+        Response::from_string(&json).with_header(content_type_header),
+    )
+}
+
+fn serve_suggest(
+    request: Request,
+    params: &HashMap<String, String>,
+    index: &RwLock<impl IndexReader>,
+) -> Result<()> {
+    let Some(prefix) = params.get("prefix") else {
+        return serve_400(request, "no prefix provided");
+    };
+    let limit = match params.get("limit").map(|limit| limit.parse()) {
+        None => 10,
+        Some(Ok(limit)) => limit,
+        Some(Err(err)) => return serve_400(request, &format!("invalid limit; {err}")),
+    };
+    let prefix = prefix.chars().collect::<Vec<_>>();
+    let completions = match complete(&*index.read().unwrap(), &prefix, limit) {
+        Ok(completions) => completions,
+        Err(err) => return serve_error(request, err),
+    };
+    let json = serde_json::to_string(&completions).expect("completions serialize");
+    let content_type_header =
+        Header::from_bytes("Content-type", "application/json").expect("No garbage in headers");
+    respond(
+        request,
+        Response::from_string(&json).with_header(content_type_header),
+    )
 }
 
-fn serve_request(model: &Model, request: Request) -> Result<(), ()> {
+fn serve_terms(
+    request: Request,
+    params: &HashMap<String, String>,
+    index: &RwLock<impl IndexReader>,
+) -> Result<()> {
+    let Some(text) = params.get("text") else {
+        return serve_400(request, "no text provided");
+    };
+    let text = text.chars().collect::<Vec<_>>();
+    let terms = match inspect_terms(&*index.read().unwrap(), &text) {
+        Ok(terms) => terms,
+        Err(err) => return serve_error(request, err),
+    };
+    let json = serde_json::to_string(&terms).expect("terms serialize");
+    let content_type_header =
+        Header::from_bytes("Content-type", "application/json").expect("No garbage in headers");
+    respond(
+        request,
+        Response::from_string(&json).with_header(content_type_header),
+    )
+}
+
+/// Location of the indexed document at `requested`. Pages are served as
+/// the whole file, the viewer takes care of the `#page=N` fragment.
+fn find_document(
+    index: &dyn IndexReader,
+    requested: &str,
+) -> Result<Option<(PathBuf, Option<String>)>> {
+    // The file itself, or an archive holding the rest as an entry. Only the
+    // documents of the candidate files are looked at.
+    let archives = requested
+        .match_indices(ENTRY_SEPARATOR)
+        .map(|(i, _)| (&requested[..i], Some(&requested[i..])));
+    for (file, wanted) in [(requested, None)].into_iter().chain(archives) {
+        for doc in index.file_docs(Path::new(file))? {
+            let (path, part) = index.doc_location(doc)?;
+            let entry = part
+                .as_deref()
+                .and_then(|part| part.split('#').next())
+                .filter(|entry| !entry.is_empty());
+            if entry == wanted {
+                return Ok(Some((path, entry.map(str::to_string))));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn read_document(path: &Path, entry: Option<&str>) -> io::Result<(Vec<u8>, &'static str)> {
+    let (content, name) = read_file_or_entry(path, entry)?;
+    let content_type = Parsers::default()
+        .mime_type(name)
+        .unwrap_or("application/octet-stream");
+    Ok((content, content_type))
+}
+
+fn serve_document(
+    request: Request,
+    params: &HashMap<String, String>,
+    index: &RwLock<impl IndexReader>,
+) -> Result<()> {
+    let Some(requested) = params.get("path") else {
+        return serve_400(request, "no document path provided");
+    };
+    // Only documents that are in the index can be read
+    let location = find_document(&*index.read().unwrap(), requested);
+    let document = match location {
+        Ok(Some((path, entry))) => read_document(&path, entry.as_deref())
+            .map_err(|err| Error::io(format!("could not read document {requested}"), err)),
+        Ok(None) => return serve_404(request),
+        Err(err) => Err(err),
+    };
+    let (content, content_type) = match document {
+        Ok(document) => document,
+        Err(err) => return serve_error(request, err),
+    };
+    let content_type_header =
+        Header::from_bytes("Content-Type", content_type).expect("No garbage in headers");
+    respond(
+        request,
+        Response::from_data(content).with_header(content_type_header),
+    )
+}
+
+fn serve_request(
+    index: &RwLock<impl IndexReader>,
+    scorers: &Scorers,
+    request: Request,
+) -> Result<()> {
+    let (path, params) = parse_url(request.url());
+    let path = path.to_string();
     match request.method() {
-        Method::Post => match request.url() {
-            "/api/search" => serve_search(request, model)?,
+        Method::Post => match path.as_str() {
+            "/api/search" => serve_search(request, &params, index, scorers)?,
             _ => serve_404(request)?,
         },
-        Method::Get => match request.url() {
+        Method::Get => match path.as_str() {
             "/" | "/index.html" => {
                 serve_static_file(request, "index.html", "text/html; charset=utf-8")?;
             }
             "/index.js" => {
                 serve_static_file(request, "src/index.js", "text/javascript; charset=utf-8")?;
             }
+            "/document" => serve_document(request, &params, index)?,
+            "/api/suggest" => serve_suggest(request, &params, index)?,
+            "/api/terms" => serve_terms(request, &params, index)?,
             _ => serve_404(request)?,
         },
         _ => serve_404(request)?,
@@ -78,15 +416,22 @@ fn serve_request(model: &Model, request: Request) -> Result<(), ()> {
     Ok(())
 }
 
-pub fn start(address: &str, model: &Model) -> Result<(), ()> {
-    let server = Server::http(&address).map_err(|err| {
-        eprintln!("Error: Couldn't start HTTP server on {address}; {err}");
+/// Serve queries against `index` at `address`, ranking searches with any of
+/// `scorers`. The index may be updated concurrently by whoever holds the
+/// write side of the lock.
+pub fn start(address: &str, index: &RwLock<impl IndexReader>, scorers: &Scorers) -> Result<()> {
+    let server = Server::http(address).map_err(|err| Error::Http {
+        context: format!("could not start HTTP server on {address}"),
+        reason: err.to_string(),
     })?;
 
     println!("Info: listening at HTTP://{address}");
 
+    // A failed request doesn't stop the server from answering the next one
     for request in server.incoming_requests() {
-        serve_request(&model, request)?
+        if let Err(err) = serve_request(index, scorers, request) {
+            eprintln!("Error: {err}");
+        }
     }
     Ok(())
 }
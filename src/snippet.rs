@@ -0,0 +1,326 @@
+//! Snippets of the text of matching documents. The matching parts of the
+//! documents are read and parsed again, and the stretches of text where most
+//! query terms matched are cut out with the matched words wrapped in
+//! `<mark>`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::archive::*;
+use crate::error::*;
+use crate::lexer::*;
+use crate::model::*;
+use crate::parser::*;
+
+/// Tokens in a snippet
+const SNIPPET_TOKENS: usize = 30;
+/// Tokens shown before the first match of a snippet
+const SNIPPET_LEAD: usize = 6;
+/// Most snippets cut out of a document
+pub const MAX_SNIPPETS: usize = 2;
+
+/// Text of the documents at `locations`, as they were indexed. Every file
+/// is read and every archive unpacked once however many of its documents are
+/// asked for, and only the parts asked for are extracted.
+pub fn document_texts(locations: &[(PathBuf, Option<String>)]) -> Vec<Result<String>> {
+    let parsers = Parsers::default();
+    let mut files = BTreeMap::<&Path, Vec<usize>>::new();
+    for (i, (path, _)) in locations.iter().enumerate() {
+        files.entry(path).or_default().push(i);
+    }
+    let mut texts = Vec::new();
+    texts.resize_with(locations.len(), || None);
+    for (path, docs) in files {
+        let parts = docs
+            .iter()
+            .map(|&i| locations[i].1.as_deref())
+            .collect::<Vec<_>>();
+        match file_texts(path, &parts, &parsers) {
+            Ok(file_texts) => {
+                for (i, text) in docs.into_iter().zip(file_texts) {
+                    texts[i] = Some(text);
+                }
+            }
+            Err(err) => {
+                for i in docs {
+                    texts[i] = Some(Err(io::Error::new(err.kind(), err.to_string())));
+                }
+            }
+        }
+    }
+    texts
+        .into_iter()
+        .zip(locations)
+        .map(|(text, (path, part))| {
+            text.expect("every document is read").map_err(|err| {
+                let path = full_path(path, part.as_deref());
+                Error::io(format!("could not read {}", path.display()), err)
+            })
+        })
+        .collect()
+}
+
+/// Text of the `parts` of the file at `path`, see [`document_texts`]
+fn file_texts(
+    path: &Path,
+    parts: &[Option<&str>],
+    parsers: &Parsers,
+) -> io::Result<Vec<io::Result<String>>> {
+    let content = fs::read(path)?;
+    // Archive entries are followed by the part of the entry, if any
+    let mut entries = BTreeMap::<Option<&str>, Vec<(usize, Option<&str>)>>::new();
+    for (i, part) in parts.iter().enumerate() {
+        let entry = part
+            .and_then(|part| part.split('#').next())
+            .filter(|entry| !entry.is_empty());
+        let entry_part = part
+            .map(|part| &part[entry.map_or(0, str::len)..])
+            .filter(|entry_part| !entry_part.is_empty());
+        let name = entry.and_then(|entry| entry.strip_prefix(ENTRY_SEPARATOR));
+        entries.entry(name).or_default().push((i, entry_part));
+    }
+
+    let mut unpacked = HashMap::new();
+    if entries.keys().any(Option::is_some) {
+        let kind = ArchiveKind::of(path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "document is not in an archive")
+        })?;
+        let wanted = |name: &str| entries.contains_key(&Some(name));
+        kind.for_each_entry(&content, wanted, |name, bytes| {
+            unpacked.entry(name.to_string()).or_insert(bytes);
+        })?;
+    }
+
+    let mut texts = Vec::new();
+    texts.resize_with(parts.len(), || {
+        Err(io::Error::new(io::ErrorKind::NotFound, "part not found"))
+    });
+    for (name, docs) in &entries {
+        let (content, name) = match name {
+            Some(name) => match unpacked.get(*name) {
+                Some(entry) => (entry, Path::new(name)),
+                None => {
+                    for (i, _) in docs {
+                        let message = format!("no {name} in the archive");
+                        texts[*i] = Err(io::Error::new(io::ErrorKind::NotFound, message));
+                    }
+                    continue;
+                }
+            },
+            None => (&content, path),
+        };
+        let parser = parsers.for_path(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Unsupported, "no parser for the document")
+        });
+        let wanted = docs.iter().map(|(_, part)| *part).collect::<Vec<_>>();
+        match parser.and_then(|parser| parser.parse_selected_parts(content, &wanted)) {
+            Ok(parsed) => {
+                for ((i, _), text) in docs.iter().zip(parsed) {
+                    if let Some(text) = text {
+                        texts[*i] = Ok(text);
+                    }
+                }
+            }
+            Err(err) => {
+                for (i, _) in docs {
+                    texts[*i] = Err(io::Error::new(err.kind(), err.to_string()));
+                }
+            }
+        }
+    }
+    Ok(texts)
+}
+
+fn escape_html(text: &str, escaped: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+}
+
+/// HTML of the tokens in `window`, runs of matched tokens in a `<mark>`
+fn render(
+    text: &[char],
+    tokens: &[Range<usize>],
+    window: Range<usize>,
+    matched: &HashSet<usize>,
+) -> String {
+    let mut html = String::new();
+    if window.start > 0 {
+        html.push('…');
+    }
+    let mut end = tokens[window.start].start;
+    let mut marked = false;
+    for i in window.clone() {
+        let between = text[end..tokens[i].start].iter().collect::<String>();
+        if marked && !matched.contains(&i) {
+            html.push_str("</mark>");
+            marked = false;
+        }
+        escape_html(&between, &mut html);
+        if !marked && matched.contains(&i) {
+            html.push_str("<mark>");
+            marked = true;
+        }
+        let token = text[tokens[i].clone()].iter().collect::<String>();
+        escape_html(&token, &mut html);
+        end = tokens[i].end;
+    }
+    if marked {
+        html.push_str("</mark>");
+    }
+    if window.end < tokens.len() {
+        html.push('…');
+    }
+    html
+}
+
+/// Up to [`MAX_SNIPPETS`] snippets of `text` around the tokens matched, given
+/// as the width and positions of every match like [`SearchResults::matches`].
+/// Documents without matches get their beginning.
+pub fn snippets(text: &str, matches: &[(usize, Vec<usize>)]) -> Vec<String> {
+    let text = text.chars().collect::<Vec<_>>();
+    let tokens = Lexer::new(&text)
+        .tokens()
+        .map(|token| token.start..token.end)
+        .collect::<Vec<_>>();
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+    let matched = matches
+        .iter()
+        .flat_map(|(width, positions)| {
+            positions
+                .iter()
+                .flat_map(move |start| *start..start + width)
+        })
+        .filter(|position| *position < tokens.len())
+        .collect::<HashSet<_>>();
+    let mut remaining = matched.iter().copied().collect::<Vec<_>>();
+    remaining.sort_unstable();
+    if remaining.is_empty() {
+        let window = 0..SNIPPET_TOKENS.min(tokens.len());
+        return vec![render(&text, &tokens, window, &matched)];
+    }
+
+    let mut windows = Vec::<Range<usize>>::new();
+    while windows.len() < MAX_SNIPPETS && !remaining.is_empty() {
+        // The window starting at the match with the most matches after it
+        let mut best = (0, 0);
+        let mut last = 0;
+        for (first, start) in remaining.iter().enumerate() {
+            while last < remaining.len() && remaining[last] < start + SNIPPET_TOKENS - SNIPPET_LEAD
+            {
+                last += 1;
+            }
+            if last - first > best.1 {
+                best = (first, last - first);
+            }
+        }
+        let first = remaining[best.0];
+        let mut start = first.saturating_sub(SNIPPET_LEAD);
+        let mut end = (start + SNIPPET_TOKENS).min(tokens.len());
+        // Snippets don't repeat each other's text
+        for other in &windows {
+            if other.start <= first && other.end > start {
+                start = other.end;
+            } else if other.start > first && other.start < end {
+                end = other.start;
+            }
+        }
+        let window = start..end;
+        remaining.retain(|position| !window.contains(position));
+        windows.push(window);
+    }
+    windows.sort_by_key(|window| window.start);
+    windows
+        .into_iter()
+        .map(|window| render(&text, &tokens, window, &matched))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `w0 w1 w2 …` with `count` words, word `i` at position `i`
+    fn words(count: usize) -> String {
+        (0..count)
+            .map(|i| format!("w{i}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn beginning_without_matches() {
+        assert_eq!(snippets("", &[]), Vec::<String>::new());
+        assert_eq!(snippets("  one two ", &[]), ["one two"]);
+        let long = snippets(&words(40), &[]);
+        assert_eq!(long, [format!("{}…", words(SNIPPET_TOKENS))]);
+    }
+
+    #[test]
+    fn lead_before_the_match() {
+        let text = words(100);
+        let html = snippets(&text, &[(1, vec![50])]);
+        assert_eq!(html.len(), 1);
+        assert!(html[0].starts_with("…w44 w45"));
+        assert!(html[0].contains("w49 <mark>w50</mark> w51"));
+        assert!(html[0].ends_with("w73…"));
+    }
+
+    #[test]
+    fn adjacent_matches_share_a_mark() {
+        let html = snippets("a b c d e f", &[(2, vec![1]), (1, vec![3, 5])]);
+        assert_eq!(html, ["a <mark>b c d</mark> e <mark>f</mark>"]);
+    }
+
+    #[test]
+    fn escapes_html() {
+        // Punctuation is a token of its own
+        let html = snippets("x < y & \"z\" <b>", &[(1, vec![2])]);
+        assert_eq!(
+            html,
+            ["x &lt; <mark>y</mark> &amp; &quot;z&quot; &lt;b&gt;"]
+        );
+    }
+
+    #[test]
+    fn snippets_do_not_overlap() {
+        let text = words(200);
+        // The densest stretch is cut out first, the lead of the other one
+        // stops where it would run into it
+        let matches = [(1, vec![20, 22, 24, 46])];
+        let html = snippets(&text, &matches);
+        assert_eq!(html.len(), MAX_SNIPPETS);
+        assert!(html[0].starts_with("…w14"));
+        assert!(html[0].ends_with("w43…"));
+        assert!(html[1].starts_with("…w44 w45 <mark>w46</mark>"));
+
+        let far = [(1, vec![10, 150, 151])];
+        let html = snippets(&text, &far);
+        assert!(html[0].contains("<mark>w10</mark>"));
+        assert!(html[1].contains("<mark>w150 w151</mark>"));
+    }
+
+    #[test]
+    fn ignores_positions_past_the_end() {
+        // The file changed since it was indexed
+        let html = snippets("short text", &[(1, vec![1, 7]), (3, vec![9])]);
+        assert_eq!(html, ["short <mark>text</mark>"]);
+    }
+
+    #[test]
+    fn counts_positions_in_tokens_of_multi_byte_text() {
+        let html = snippets("ünïcödé wörds — ok", &[(1, vec![1])]);
+        assert_eq!(html, ["ünïcödé <mark>wörds</mark> — ok"]);
+    }
+}
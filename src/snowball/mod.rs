@@ -1,5 +1,22 @@
+// Vendored Snowball runtime and generated stemmers, kept as upstream emits
+// them. Only the lints the generated code is known to trip are allowed.
+#![allow(
+    clippy::assign_op_pattern,
+    clippy::collapsible_if,
+    clippy::if_same_then_else,
+    clippy::mut_range_bound,
+    clippy::needless_borrow,
+    clippy::needless_return,
+    clippy::never_loop,
+    clippy::nonminimal_bool,
+    clippy::redundant_static_lifetimes,
+    clippy::type_complexity
+)]
+
 pub mod algorithms;
 mod among;
+// The English stemmer doesn't use all of the runtime
+#[allow(dead_code)]
 mod snowball_env;
 
 pub use crate::snowball::among::Among;
@@ -0,0 +1,778 @@
+//! Binary on-disk index format.
+//!
+//! All integers are little endian. The file starts with a fixed size header
+//! followed by the document paths, the document table, the term records,
+//! the term table and the path table:
+//!
+//! ```text
+//! header     magic "RSIX", version u32, doc count u64, term count u64,
+//!            total doc length u64, doc table offset u64, term table offset u64,
+//!            path table offset u64
+//! path       varint path length, path bytes, varint part length, part bytes
+//! doc table  (u64 path offset, u64 length, f32 norm, u64 mtime, u64 size,
+//!            u64 content hash) for every document, by id
+//! term       varint term length, term bytes, varint df,
+//!            varint postings size in bytes, postings,
+//!            varint forms size in bytes, forms
+//! postings   (varint doc id delta, varint tf, varint position count,
+//!            varint position delta for every position, varint form count,
+//!            (varint form index, varint count) for every form) for every
+//!            posting
+//! forms      (varint form length, form bytes, varint count) for every
+//!            surface form of the term, sorted by form, the counts summed
+//!            over the postings
+//! term table u64 offset of every term record, sorted by term
+//! path table u64 id of every document, sorted by path
+//! ```
+//!
+//! Every table has fixed width entries so a memory mapped index can be
+//! queried in place, see [`MappedIndex`].
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
+
+use crate::error::{Error, Result};
+use crate::model::*;
+use crate::scorer::*;
+
+pub const MAGIC: &[u8; 4] = b"RSIX";
+pub const VERSION: u32 = 8;
+const HEADER_SIZE: usize = 56;
+const DOC_ENTRY_SIZE: usize = 44;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn encode_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn encode_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    encode_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+/// Reads the primitives of the format from a byte slice
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8], pos: usize) -> Self {
+        Self { bytes, pos }
+    }
+
+    pub fn read_bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| invalid_data("unexpected end of index"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Bytes left to read
+    pub fn remaining(&self) -> usize {
+        self.bytes.len().saturating_sub(self.pos)
+    }
+
+    pub fn read_u32(&mut self) -> io::Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> io::Result<u64> {
+        let bytes = self.read_bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_f32(&mut self) -> io::Result<f32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(f32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_varint(&mut self) -> io::Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_bytes(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_data("varint is too long"))
+    }
+
+    pub fn read_usize(&mut self) -> io::Result<usize> {
+        usize::try_from(self.read_varint()?).map_err(|_| invalid_data("value out of range"))
+    }
+
+    pub fn read_str(&mut self) -> io::Result<&'a str> {
+        let len = self.read_usize()?;
+        std::str::from_utf8(self.read_bytes(len)?).map_err(|_| invalid_data("invalid UTF-8"))
+    }
+}
+
+pub struct Header {
+    pub doc_count: usize,
+    pub term_count: usize,
+    pub total_length: usize,
+    pub doc_table: usize,
+    pub term_table: usize,
+    pub path_table: usize,
+}
+
+impl Header {
+    pub fn read(bytes: &[u8]) -> io::Result<Self> {
+        let mut decoder = Decoder::new(bytes, 0);
+        if decoder.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("not a binary index file"));
+        }
+        let version = decoder.read_u32()?;
+        if version != VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported index version {version}, expected {VERSION}"
+            )));
+        }
+        let mut read_offset = || -> io::Result<usize> {
+            usize::try_from(decoder.read_u64()?).map_err(|_| invalid_data("offset out of range"))
+        };
+        Ok(Self {
+            doc_count: read_offset()?,
+            term_count: read_offset()?,
+            total_length: read_offset()?,
+            doc_table: read_offset()?,
+            term_table: read_offset()?,
+            path_table: read_offset()?,
+        })
+    }
+
+    fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(MAGIC);
+        buffer.extend_from_slice(&VERSION.to_le_bytes());
+        let values = [
+            self.doc_count,
+            self.term_count,
+            self.total_length,
+            self.doc_table,
+            self.term_table,
+            self.path_table,
+        ];
+        for value in values {
+            buffer.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+    }
+}
+
+pub fn is_binary_index(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Offset of the `i`th entry of a u64 offset table
+pub fn table_entry(bytes: &[u8], table: usize, i: usize) -> io::Result<usize> {
+    let entry = i
+        .checked_mul(8)
+        .and_then(|entry| entry.checked_add(table))
+        .ok_or_else(|| invalid_data("offset out of range"))?;
+    let offset = Decoder::new(bytes, entry).read_u64()?;
+    usize::try_from(offset).map_err(|_| invalid_data("offset out of range"))
+}
+
+/// Offset of the field `field` bytes into the doc table entry of `doc`
+fn doc_entry(header: &Header, doc: DocId, field: usize) -> io::Result<usize> {
+    if doc >= header.doc_count {
+        return Err(invalid_data("document id out of range"));
+    }
+    doc.checked_mul(DOC_ENTRY_SIZE)
+        .and_then(|entry| entry.checked_add(header.doc_table))
+        .and_then(|entry| entry.checked_add(field))
+        .ok_or_else(|| invalid_data("offset out of range"))
+}
+
+fn read_doc_stats(bytes: &[u8], header: &Header, doc: DocId) -> io::Result<DocStats> {
+    let mut decoder = Decoder::new(bytes, doc_entry(header, doc, 8)?);
+    Ok(DocStats {
+        length: decoder.read_u64()? as usize,
+        norm: decoder.read_f32()?,
+    })
+}
+
+fn read_doc_stamp(bytes: &[u8], header: &Header, doc: DocId) -> io::Result<FileStamp> {
+    let mut decoder = Decoder::new(bytes, doc_entry(header, doc, 20)?);
+    Ok(FileStamp {
+        mtime: decoder.read_u64()?,
+        size: decoder.read_u64()?,
+        hash: decoder.read_u64()?,
+    })
+}
+
+/// Decoder positioned at the path of the document, followed by its part
+fn doc_path_decoder<'a>(bytes: &'a [u8], header: &Header, doc: DocId) -> io::Result<Decoder<'a>> {
+    let offset = Decoder::new(bytes, doc_entry(header, doc, 0)?).read_u64()?;
+    let offset = usize::try_from(offset).map_err(|_| invalid_data("offset out of range"))?;
+    Ok(Decoder::new(bytes, offset))
+}
+
+/// File and part of the file the document comes from
+fn read_doc_location(
+    bytes: &[u8],
+    header: &Header,
+    doc: DocId,
+) -> io::Result<(PathBuf, Option<String>)> {
+    let mut decoder = doc_path_decoder(bytes, header, doc)?;
+    let path = PathBuf::from(decoder.read_str()?);
+    let part = Some(decoder.read_str()?)
+        .filter(|part| !part.is_empty())
+        .map(str::to_string);
+    Ok((path, part))
+}
+
+/// Term record at `offset`, returned as the term, its df, its encoded
+/// postings and its encoded surface forms
+pub fn read_term(bytes: &[u8], offset: usize) -> io::Result<(&str, usize, &[u8], &[u8])> {
+    let mut decoder = Decoder::new(bytes, offset);
+    let term = decoder.read_str()?;
+    let df = decoder.read_usize()?;
+    let size = decoder.read_usize()?;
+    let postings = decoder.read_bytes(size)?;
+    let size = decoder.read_usize()?;
+    Ok((term, df, postings, decoder.read_bytes(size)?))
+}
+
+/// Postings of a term with `df` documents. The surface forms of every
+/// posting are only decoded when the `forms` of the term are given.
+pub fn decode_postings(
+    bytes: &[u8],
+    df: usize,
+    forms: Option<&[(String, usize)]>,
+) -> io::Result<Vec<Posting>> {
+    let mut decoder = Decoder::new(bytes, 0);
+    // Counts come from the file, every entry takes at least a byte
+    let mut postings = Vec::with_capacity(df.min(decoder.remaining()));
+    let add = |value: usize, delta: usize| {
+        value
+            .checked_add(delta)
+            .ok_or_else(|| invalid_data("value out of range"))
+    };
+    let mut doc = 0;
+    for _ in 0..df {
+        doc = add(doc, decoder.read_usize()?)?;
+        let tf = decoder.read_usize()?;
+        let count = decoder.read_usize()?;
+        let mut positions = Vec::with_capacity(count.min(decoder.remaining()));
+        let mut position = 0;
+        for _ in 0..count {
+            position = add(position, decoder.read_usize()?)?;
+            positions.push(position);
+        }
+        let count = decoder.read_usize()?;
+        let mut posting_forms = Vec::new();
+        for _ in 0..count {
+            let index = decoder.read_usize()?;
+            let count = decoder.read_usize()?;
+            if let Some(forms) = forms {
+                let (form, _) = forms
+                    .get(index)
+                    .ok_or_else(|| invalid_data("surface form out of range"))?;
+                posting_forms.push((form.clone(), count));
+            }
+        }
+        postings.push(Posting {
+            doc,
+            tf,
+            positions,
+            forms: posting_forms,
+        });
+    }
+    Ok(postings)
+}
+
+pub fn decode_forms(bytes: &[u8]) -> io::Result<Vec<(String, usize)>> {
+    let mut decoder = Decoder::new(bytes, 0);
+    let mut forms = Vec::new();
+    while decoder.pos < bytes.len() {
+        let form = decoder.read_str()?.to_string();
+        forms.push((form, decoder.read_usize()?));
+    }
+    Ok(forms)
+}
+
+fn encode_forms(buffer: &mut Vec<u8>, forms: &BTreeMap<String, usize>) {
+    for (form, count) in forms {
+        encode_bytes(buffer, form.as_bytes());
+        encode_varint(buffer, *count as u64);
+    }
+}
+
+/// Encode `postings`, their surface forms as indexes into `forms`
+fn encode_postings(buffer: &mut Vec<u8>, postings: &[Posting], forms: &BTreeMap<String, usize>) {
+    let index = forms
+        .keys()
+        .enumerate()
+        .map(|(i, form)| (form.as_str(), i))
+        .collect::<HashMap<_, _>>();
+    let mut prev = 0;
+    for posting in postings {
+        encode_varint(buffer, (posting.doc - prev) as u64);
+        encode_varint(buffer, posting.tf as u64);
+        encode_varint(buffer, posting.positions.len() as u64);
+        let mut prev_position = 0;
+        for &position in &posting.positions {
+            encode_varint(buffer, (position - prev_position) as u64);
+            prev_position = position;
+        }
+        encode_varint(buffer, posting.forms.len() as u64);
+        for (form, count) in &posting.forms {
+            encode_varint(buffer, index[form.as_str()] as u64);
+            encode_varint(buffer, *count as u64);
+        }
+        prev = posting.doc;
+    }
+}
+
+pub fn save_model_as_binary(index_path: &Path, model: &Model) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(index_path)?);
+    file.write_all(&[0; HEADER_SIZE])?;
+    let mut pos = HEADER_SIZE;
+    let mut buffer = Vec::new();
+
+    let mut path_offsets = Vec::with_capacity(model.docs.len());
+    let mut paths = Vec::with_capacity(model.docs.len());
+    for doc in &model.docs {
+        buffer.clear();
+        // Indexing skips files whose path isn't UTF-8, see `plan_update`
+        let path = doc
+            .path
+            .to_str()
+            .ok_or_else(|| invalid_data("document path is not UTF-8"))?;
+        paths.push(path);
+        encode_bytes(&mut buffer, path.as_bytes());
+        encode_bytes(&mut buffer, doc.part.as_deref().unwrap_or("").as_bytes());
+        path_offsets.push(pos);
+        file.write_all(&buffer)?;
+        pos += buffer.len();
+    }
+    let doc_table = pos;
+    for (doc, offset) in model.docs.iter().zip(path_offsets) {
+        file.write_all(&(offset as u64).to_le_bytes())?;
+        file.write_all(&(doc.length as u64).to_le_bytes())?;
+        file.write_all(&doc.norm.to_le_bytes())?;
+        for value in [doc.stamp.mtime, doc.stamp.size, doc.stamp.hash] {
+            file.write_all(&value.to_le_bytes())?;
+        }
+    }
+    pos += model.docs.len() * DOC_ENTRY_SIZE;
+
+    // Terms are sorted already, the order of the term table
+    let terms = &model.postings;
+    let mut term_offsets = Vec::with_capacity(terms.len());
+    let mut encoded = Vec::new();
+    for (term, postings) in terms {
+        buffer.clear();
+        encode_bytes(&mut buffer, term.as_bytes());
+        encode_varint(&mut buffer, postings.len() as u64);
+        let forms = total_forms(postings.iter());
+        encoded.clear();
+        encode_postings(&mut encoded, postings, &forms);
+        encode_bytes(&mut buffer, &encoded);
+        encoded.clear();
+        encode_forms(&mut encoded, &forms);
+        encode_bytes(&mut buffer, &encoded);
+        term_offsets.push(pos);
+        file.write_all(&buffer)?;
+        pos += buffer.len();
+    }
+    let term_table = pos;
+    for offset in &term_offsets {
+        file.write_all(&(*offset as u64).to_le_bytes())?;
+    }
+    pos += term_offsets.len() * 8;
+
+    // Compared as the mapped index compares them, byte by byte
+    let path_table = pos;
+    let mut by_path = (0..model.docs.len()).collect::<Vec<_>>();
+    by_path.sort_by_key(|doc| paths[*doc]);
+    for doc in by_path {
+        file.write_all(&(doc as u64).to_le_bytes())?;
+    }
+
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    Header {
+        doc_count: model.docs.len(),
+        term_count: term_offsets.len(),
+        total_length: model.docs.iter().map(|doc| doc.length).sum(),
+        doc_table,
+        term_table,
+        path_table,
+    }
+    .write(&mut header);
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    file.flush()
+}
+
+pub fn decode_model(bytes: &[u8]) -> io::Result<Model> {
+    let header = Header::read(bytes)?;
+    let mut model = Model::default();
+    for doc in 0..header.doc_count {
+        let stats = read_doc_stats(bytes, &header, doc)?;
+        let (path, part) = read_doc_location(bytes, &header, doc)?;
+        model.docs.push(Document {
+            path,
+            part,
+            stamp: read_doc_stamp(bytes, &header, doc)?,
+            length: stats.length,
+            norm: stats.norm,
+        });
+    }
+    for i in 0..header.term_count {
+        let offset = table_entry(bytes, header.term_table, i)?;
+        let (term, df, postings, forms) = read_term(bytes, offset)?;
+        let postings = decode_postings(postings, df, Some(&decode_forms(forms)?))?;
+        // Loaded models index their documents directly
+        if postings
+            .iter()
+            .any(|posting| posting.doc >= header.doc_count)
+        {
+            return Err(invalid_data("document id out of range"));
+        }
+        model.postings.insert(term.to_string(), postings);
+    }
+    Ok(model)
+}
+
+/// Binary index queried in place through a memory map. Opening only reads
+/// the header, everything else is decoded when a query touches it, and
+/// every process mapping the same file shares its pages.
+pub struct MappedIndex {
+    path: PathBuf,
+    mmap: Mmap,
+    header: Header,
+}
+
+impl MappedIndex {
+    pub fn open(index_path: &Path) -> Result<Self> {
+        let file = File::open(index_path).map_err(|err| Error::index_io(index_path, err))?;
+        // SAFETY: index files are never modified in place, `save_index`
+        // replaces them in every format by renaming a new file over the
+        // old one, see `replace_index`.
+        let mmap = unsafe { Mmap::map(&file) }.map_err(|err| Error::index_io(index_path, err))?;
+        let header = Header::read(&mmap).map_err(|err| Error::index_format(index_path, err))?;
+        Ok(Self {
+            path: index_path.to_path_buf(),
+            mmap,
+            header,
+        })
+    }
+
+    /// Records past the header are only decoded when queried, so a
+    /// corrupt index shows up as failing reads
+    fn corrupt(&self, err: io::Error) -> Error {
+        Error::index_format(&self.path, err)
+    }
+
+    /// Term at position `i` of the term table
+    fn term_at(&self, i: usize) -> io::Result<&str> {
+        let offset = table_entry(&self.mmap, self.header.term_table, i)?;
+        Decoder::new(&self.mmap, offset).read_str()
+    }
+
+    /// Position of the first term in the term table that is not less than
+    /// `term`
+    fn lower_bound(&self, term: &str) -> io::Result<usize> {
+        let (mut lo, mut hi) = (0, self.header.term_count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.term_at(mid)? < term {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok(lo)
+    }
+
+    /// Id and path of the document at position `i` of the path table
+    fn path_at(&self, i: usize) -> io::Result<(DocId, &str)> {
+        let doc = table_entry(&self.mmap, self.header.path_table, i)?;
+        Ok((
+            doc,
+            doc_path_decoder(&self.mmap, &self.header, doc)?.read_str()?,
+        ))
+    }
+
+    /// Position of `term` in the term table
+    fn find_term(&self, term: &str) -> io::Result<Option<usize>> {
+        let i = self.lower_bound(term)?;
+        if i < self.header.term_count && self.term_at(i)? == term {
+            return Ok(Some(i));
+        }
+        Ok(None)
+    }
+}
+
+impl IndexReader for MappedIndex {
+    fn corpus_stats(&self) -> CorpusStats {
+        CorpusStats {
+            doc_count: self.header.doc_count,
+            avg_doc_length: self.header.total_length as f32 / self.header.doc_count.max(1) as f32,
+        }
+    }
+
+    fn doc_stats(&self, doc: DocId) -> Result<DocStats> {
+        read_doc_stats(&self.mmap, &self.header, doc).map_err(|err| self.corrupt(err))
+    }
+
+    fn doc_location(&self, doc: DocId) -> Result<(PathBuf, Option<String>)> {
+        read_doc_location(&self.mmap, &self.header, doc).map_err(|err| self.corrupt(err))
+    }
+
+    fn file_docs(&self, path: &Path) -> Result<Vec<DocId>> {
+        let Some(path) = path.to_str() else {
+            return Ok(Vec::new());
+        };
+        let read = || -> io::Result<Vec<DocId>> {
+            let (mut lo, mut hi) = (0, self.header.doc_count);
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                if self.path_at(mid)?.1 < path {
+                    lo = mid + 1;
+                } else {
+                    hi = mid;
+                }
+            }
+            let mut docs = Vec::new();
+            for i in lo..self.header.doc_count {
+                let (doc, other) = self.path_at(i)?;
+                if other != path {
+                    break;
+                }
+                docs.push(doc);
+            }
+            Ok(docs)
+        };
+        read().map_err(|err| self.corrupt(err))
+    }
+
+    fn postings(&self, term: &str) -> Result<Cow<'_, [Posting]>> {
+        let read = || -> io::Result<Vec<Posting>> {
+            let Some(i) = self.find_term(term)? else {
+                return Ok(Vec::new());
+            };
+            let offset = table_entry(&self.mmap, self.header.term_table, i)?;
+            let (_, df, postings, _) = read_term(&self.mmap, offset)?;
+            decode_postings(postings, df, None)
+        };
+        read().map(Cow::Owned).map_err(|err| self.corrupt(err))
+    }
+
+    fn df(&self, term: &str) -> Result<usize> {
+        let read = || -> io::Result<usize> {
+            let Some(i) = self.find_term(term)? else {
+                return Ok(0);
+            };
+            let offset = table_entry(&self.mmap, self.header.term_table, i)?;
+            Ok(read_term(&self.mmap, offset)?.1)
+        };
+        read().map_err(|err| self.corrupt(err))
+    }
+
+    fn surface_forms(&self, term: &str) -> Result<Vec<(String, usize)>> {
+        let read = || -> io::Result<Vec<(String, usize)>> {
+            let Some(i) = self.find_term(term)? else {
+                return Ok(Vec::new());
+            };
+            let offset = table_entry(&self.mmap, self.header.term_table, i)?;
+            decode_forms(read_term(&self.mmap, offset)?.3)
+        };
+        read()
+            .map(|forms| most_common_first(forms.into_iter()))
+            .map_err(|err| self.corrupt(err))
+    }
+
+    fn visit_terms_from(&self, from: &str, visit: &mut dyn FnMut(&str) -> bool) -> Result<()> {
+        let mut visit_range = || -> io::Result<()> {
+            for i in self.lower_bound(from)?..self.header.term_count {
+                if !visit(self.term_at(i)?) {
+                    break;
+                }
+            }
+            Ok(())
+        };
+        visit_range().map_err(|err| self.corrupt(err))
+    }
+}
+
+/// Write the index with `save` next to `index_path` and move it into place,
+/// so servers that have the old file mapped keep reading a consistent
+/// snapshot, whatever the format of the new one.
+pub fn replace_index(
+    index_path: &Path,
+    save: impl FnOnce(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut tmp_path = index_path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    save(&tmp_path)?;
+    fs::rename(&tmp_path, index_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn add(model: &mut Model, path: &str, terms: &[(&str, &[usize])], forms: &[(&str, &str)]) {
+        let terms = terms
+            .iter()
+            .map(|(term, positions)| (term.to_string(), positions.to_vec()))
+            .collect::<HashMap<_, _>>();
+        let mut surface = SurfaceForms::new();
+        for (term, form) in forms {
+            *surface
+                .entry(term.to_string())
+                .or_default()
+                .entry(form.to_string())
+                .or_default() += 1;
+        }
+        let stamp = FileStamp {
+            mtime: model.docs.len() as u64,
+            size: 100,
+            hash: u64::MAX,
+        };
+        model.add_document(PathBuf::from(path), None, stamp, terms, surface);
+    }
+
+    fn model() -> Model {
+        let mut model = Model::default();
+        let running = [("run", "running"), ("run", "running")];
+        add(
+            &mut model,
+            "a.txt",
+            &[("run", &[0, 3]), ("fast", &[1])],
+            &running,
+        );
+        add(&mut model, "b.txt", &[("run", &[200])], &[("run", "runs")]);
+        add(&mut model, "c.txt", &[("slow", &[0, 1, 2])], &[]);
+        model
+    }
+
+    fn save(model: &Model, name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}.bin", std::process::id()));
+        save_model_as_binary(&path, model).unwrap();
+        path
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        let values = [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX];
+        let mut buffer = Vec::new();
+        for value in values {
+            encode_varint(&mut buffer, value);
+        }
+        let mut decoder = Decoder::new(&buffer, 0);
+        for value in values {
+            assert_eq!(decoder.read_varint().unwrap(), value);
+        }
+        assert_eq!(decoder.remaining(), 0);
+        assert!(decoder.read_varint().is_err());
+    }
+
+    #[test]
+    fn rejects_overlong_varints() {
+        assert!(Decoder::new(&[0xff; 11], 0).read_varint().is_err());
+    }
+
+    #[test]
+    fn rejects_counts_past_the_end() {
+        assert!(decode_postings(&[], usize::MAX, None).is_err());
+        // A position count no file could hold
+        let mut buffer = Vec::new();
+        for value in [0, 1, u64::MAX] {
+            encode_varint(&mut buffer, value);
+        }
+        assert!(decode_postings(&buffer, 1, None).is_err());
+        // Deltas adding up past the largest id
+        let mut buffer = Vec::new();
+        for value in [u64::MAX, 0, 0, 0, 1, 0, 0, 0] {
+            encode_varint(&mut buffer, value);
+        }
+        assert!(decode_postings(&buffer, 2, None).is_err());
+        // A surface form the term doesn't have
+        let mut buffer = Vec::new();
+        for value in [0, 1, 0, 1, 5, 1] {
+            encode_varint(&mut buffer, value);
+        }
+        assert!(decode_postings(&buffer, 1, None).is_ok());
+        assert!(decode_postings(&buffer, 1, Some(&[])).is_err());
+    }
+
+    #[test]
+    fn model_round_trip() {
+        let model = model();
+        let path = save(&model, "model-round-trip");
+        let decoded = decode_model(&fs::read(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            serde_json::to_value(decoded.unwrap()).unwrap(),
+            serde_json::to_value(&model).unwrap()
+        );
+    }
+
+    #[test]
+    fn mapped_index_reads_like_the_model() {
+        let model = model();
+        let path = save(&model, "mapped-index");
+        let index = MappedIndex::open(&path).unwrap();
+        for term in ["fast", "run", "slow", "walk"] {
+            // Mapped postings leave the surface forms to `surface_forms`
+            let mut postings = model.postings(term).unwrap().into_owned();
+            for posting in &mut postings {
+                posting.forms.clear();
+            }
+            let mapped = serde_json::to_value(index.postings(term).unwrap()).unwrap();
+            assert_eq!(mapped, serde_json::to_value(postings).unwrap());
+            assert_eq!(
+                index.surface_forms(term).unwrap(),
+                model.surface_forms(term).unwrap()
+            );
+        }
+        assert_eq!(index.doc_location(1).unwrap().0, PathBuf::from("b.txt"));
+        assert!(index.doc_stats(3).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mapped_index_finds_the_documents_of_a_file() {
+        let mut model = model();
+        for page in ["#page=1", "#page=2"] {
+            let terms = TermPositions::from([("walk".to_string(), vec![0])]);
+            let stamp = FileStamp::default();
+            let part = Some(page.to_string());
+            model.add_document("b.pdf".into(), part, stamp, terms, SurfaceForms::new());
+        }
+        let path = save(&model, "file-docs");
+        let index = MappedIndex::open(&path).unwrap();
+        for file in ["a.txt", "b.txt", "b.pdf", "c.txt", "b", "d.txt", ""] {
+            let mut docs = index.file_docs(Path::new(file)).unwrap();
+            docs.sort_unstable();
+            assert_eq!(docs, model.file_docs(Path::new(file)).unwrap());
+        }
+        assert_eq!(index.file_docs(Path::new("b.pdf")).unwrap().len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
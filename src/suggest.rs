@@ -0,0 +1,127 @@
+//! Suggestions from the indexed terms. Words of the query missing from the
+//! index are replaced by the closest indexed term, where a term one edit
+//! further away has to be in many more documents to be preferred. Partly
+//! typed words are completed into the indexed words most documents contain.
+
+use crate::error::*;
+use crate::fuzzy::*;
+use crate::lexer::*;
+use crate::model::*;
+use crate::query::*;
+
+/// How much every edit weighs down the document frequency of a correction
+const EDIT_DECAY: f32 = 0.1;
+
+/// Most terms a word is completed to before ranking, the rest are ignored
+const MAX_COMPLETION_TERMS: usize = 4096;
+
+/// Most likely indexed term the misspelled `term` was meant to be, as the
+/// word it is most often stemmed from
+pub fn suggest_term(index: &dyn IndexReader, term: &str) -> Result<Option<String>> {
+    let mut best = None;
+    let mut best_weight = 0.0;
+    for (candidate, distance) in fuzzy_terms(index, term, auto_edits(term), MAX_EXPANSIONS)? {
+        let df = index.df(&candidate)?;
+        let weight = df as f32 * EDIT_DECAY.powi(distance as i32);
+        if weight > best_weight {
+            best = Some(candidate);
+            best_weight = weight;
+        }
+    }
+    best.map(|term| index.surface_form(&term)).transpose()
+}
+
+/// The query with every word missing from the index corrected, if any of
+/// them could be
+pub fn suggest_query(query: &[char], index: &dyn IndexReader) -> Result<Option<String>> {
+    let mut suggestion = Vec::with_capacity(query.len());
+    let mut corrected = false;
+    let mut end = 0;
+    for (range, term) in query_words(query) {
+        if index.df(&term)? > 0 {
+            continue;
+        }
+        let Some(correction) = suggest_term(index, &term)? else {
+            continue;
+        };
+        suggestion.extend_from_slice(&query[end..range.start]);
+        suggestion.extend(correction.chars());
+        end = range.end;
+        corrected = true;
+    }
+    suggestion.extend_from_slice(&query[end..]);
+    Ok(corrected.then(|| suggestion.into_iter().collect()))
+}
+
+/// Indexed terms `word` may be the beginning of. The stem of a partly typed
+/// word is usually the beginning of the stem of the whole word, unless the
+/// whole word was stemmed to fewer characters than were typed.
+fn completion_terms(index: &dyn IndexReader, word: &str, stem: &str) -> Result<Vec<String>> {
+    let mut terms = Vec::new();
+    index.visit_terms(stem, &mut |term| {
+        terms.push(term.to_string());
+        terms.len() < MAX_COMPLETION_TERMS
+    })?;
+    for (end, _) in word.char_indices().skip(1) {
+        let shorter = &word[..end];
+        if !shorter.starts_with(stem) && index.df(shorter)? > 0 {
+            terms.push(shorter.to_string());
+        }
+    }
+    Ok(terms)
+}
+
+/// Completions of the last word of `prefix` with the number of documents
+/// containing them, most common first. The rest of `prefix` is kept in front
+/// of every completion, and its last word must occur right before the
+/// completed one.
+pub fn complete(
+    index: &dyn IndexReader,
+    prefix: &[char],
+    limit: usize,
+) -> Result<Vec<(String, usize)>> {
+    let start = prefix
+        .iter()
+        .rposition(|c| c.is_whitespace())
+        .map_or(0, |i| i + 1);
+    let (head, word) = prefix.split_at(start);
+    // Only words the lexer keeps whole can be completed
+    if word.is_empty() || !word.iter().all(|c| c.is_alphanumeric()) {
+        return Ok(Vec::new());
+    }
+    let stem = Lexer::new(word).next().expect("a word is a term");
+    let word = word
+        .iter()
+        .map(|c| c.to_ascii_lowercase())
+        .collect::<String>();
+
+    let mut ranked = Vec::new();
+    for term in completion_terms(index, &word, &stem)? {
+        ranked.push((index.df(&term)?, term));
+    }
+    ranked.sort_by(|(df1, term1), (df2, term2)| df2.cmp(df1).then(term1.cmp(term2)));
+    if let Some(previous) = Lexer::new(head).last() {
+        // Only the most common terms are looked up as phrases
+        ranked.truncate(MAX_EXPANSIONS);
+        for (df, term) in ranked.iter_mut() {
+            *df = phrase_postings(index, &[previous.clone(), term.clone()])?.len();
+        }
+        ranked.retain(|(df, _)| *df > 0);
+        ranked.sort_by(|(df1, term1), (df2, term2)| df2.cmp(df1).then(term1.cmp(term2)));
+    }
+
+    let head = head.iter().collect::<String>();
+    let mut completions = Vec::new();
+    for (df, term) in ranked {
+        if completions.len() == limit {
+            break;
+        }
+        // Forms of the same stem that don't fit what was typed are left out
+        let forms = index.surface_forms(&term)?;
+        let Some((form, _)) = forms.into_iter().find(|(form, _)| form.starts_with(&word)) else {
+            continue;
+        };
+        completions.push((format!("{head}{form}"), df));
+    }
+    Ok(completions)
+}
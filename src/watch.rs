@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::error::*;
+use crate::indexer::*;
+use crate::model::*;
+
+/// How long to wait for more events before applying a batch of changes
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// A folder being watched for changes, see [`watch_folder`]
+pub struct FolderWatcher {
+    dir_path: PathBuf,
+    /// Events stop once the watcher is dropped
+    _watcher: RecommendedWatcher,
+    receiver: Receiver<notify::Result<Event>>,
+}
+
+/// Start watching `dir_path` for changes, failing right away if it can't
+/// be watched. Changes are only applied once [`FolderWatcher::run`] is
+/// called, none are missed in between.
+pub fn watch_folder(dir_path: &Path) -> Result<FolderWatcher> {
+    let watch_error = |err: notify::Error| Error::Watch {
+        path: dir_path.to_path_buf(),
+        reason: err.to_string(),
+    };
+    let (sender, receiver) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+    watcher
+        .watch(dir_path, RecursiveMode::Recursive)
+        .map_err(watch_error)?;
+    println!("Info: watching {} for changes", dir_path.display());
+    Ok(FolderWatcher {
+        dir_path: dir_path.to_path_buf(),
+        _watcher: watcher,
+        receiver,
+    })
+}
+
+/// Print what an update of the watched folder changed
+pub fn report_update(stats: &UpdateStats) {
+    println!(
+        "Info: index updated: {} added, {} changed, {} removed",
+        stats.added, stats.changed, stats.removed
+    );
+    report_failures(&stats.failures);
+}
+
+impl FolderWatcher {
+    /// Keep the documents of the watched folder in `model` in sync with the
+    /// filesystem, choosing and reading files as `options` say. Runs until
+    /// the watcher stops delivering events.
+    pub fn run(self, model: &RwLock<Model>, options: &IndexOptions) {
+        let dir_path = self.dir_path.as_path();
+        // Events come with absolute paths, the index has them as they were
+        // spelled when indexing
+        let root = env::current_dir()
+            .map(|cwd| cwd.join(dir_path))
+            .unwrap_or_else(|_| dir_path.to_path_buf());
+        while let Ok(event) = self.receiver.recv() {
+            let mut changed = BTreeSet::new();
+            collect_event_paths(event, &mut changed);
+            while let Ok(event) = self.receiver.recv_timeout(DEBOUNCE) {
+                collect_event_paths(event, &mut changed);
+            }
+            if changed.is_empty() {
+                continue;
+            }
+
+            let changed = changed
+                .into_iter()
+                .map(|path| match path.strip_prefix(&root) {
+                    Ok(relative) => dir_path.join(relative),
+                    Err(_) => path,
+                })
+                .collect::<Vec<_>>();
+            // Only this thread changes the model, so it stays as planned
+            // while the files are parsed, and searches only wait for it to
+            // be applied
+            let plan = plan_update(dir_path, &changed, &model.read().unwrap(), options);
+            let update = plan.parse(options);
+            report_update(&update.apply(&mut model.write().unwrap()));
+        }
+    }
+}
+
+fn collect_event_paths(event: notify::Result<Event>, changed: &mut BTreeSet<PathBuf>) {
+    match event {
+        Ok(event) => {
+            if matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            ) {
+                changed.extend(event.paths);
+            }
+        }
+        Err(err) => eprintln!("Error: filesystem watcher failed; {err}"),
+    }
+}